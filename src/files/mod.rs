@@ -1,6 +1,10 @@
+pub mod region;
+
 use std::fs::File;
 use std::io::{self, Read, Write};
 
+pub use region::{RegionError, RegionStorage};
+
 
 /// Запись в бинарный файл
 pub fn write_binary_file(filename: &str, data: &[u8]) -> io::Result<()> {
@@ -13,4 +17,12 @@ pub fn write_binary_file(filename: &str, data: &[u8]) -> io::Result<()> {
 pub fn read_binary_file(filename: &str, data: &mut [u8]) -> io::Result<usize> {
     let mut input = File::open(filename)?;
     input.read(data)
-}
\ No newline at end of file
+}
+
+/// Чтение бинарного файла целиком
+pub fn read_whole_file(filename: &str) -> io::Result<Vec<u8>> {
+    let mut input = File::open(filename)?;
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    Ok(data)
+}
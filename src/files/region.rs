@@ -0,0 +1,469 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use super::{read_whole_file, write_binary_file};
+
+
+/// Размер региона в чанках по каждой оси
+pub const REGION_SIZE: isize = 8;
+const REGION_VOL: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+const MAGIC: &[u8; 4] = b"VCRG";
+const WORLD_MAGIC: &[u8; 4] = b"VCWD";
+/// Текущая версия формата файлов региона и мира
+pub const FORMAT_VERSION: u32 = 1;
+/// magic + версия + сид + координаты региона
+const HEADER_SIZE: usize = 4 + 4 + 4 + 3 * 4;
+/// magic + версия + сид + время суток
+const WORLD_SIZE: usize = 4 + 4 + 4 + 4;
+const INDEX_SIZE: usize = REGION_VOL * 8;
+
+
+#[derive(Debug)]
+pub enum RegionError {
+    Io(io::Error),
+    /// Файл или запись оборвались раньше, чем ожидалось
+    Truncated { needed: usize, len: usize },
+    /// Файл фиксированного размера оказался другой длины
+    SizeMismatch { expected: usize, len: usize },
+    BadMagic,
+    UnknownVersion(u32),
+    SeedMismatch { expected: u32, found: u32 },
+    /// Координаты в файле не совпадают с теми, где его (или запись) искали
+    PositionMismatch { expected: (isize, isize, isize), found: (isize, isize, isize) },
+    /// Запись чанка повреждена или в неизвестной кодировке
+    BadRecord(String),
+}
+
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegionError::Io(err) => write!(f, "io error: {}", err),
+            RegionError::Truncated { needed, len } => {
+                write!(f, "file is truncated: needed {} bytes, got {}", needed, len)
+            }
+            RegionError::SizeMismatch { expected, len } => {
+                write!(f, "file has {} bytes, expected exactly {}", len, expected)
+            }
+            RegionError::BadMagic => write!(f, "not a region or world file"),
+            RegionError::UnknownVersion(version) => write!(f, "unknown world format version {}", version),
+            RegionError::SeedMismatch { expected, found } => {
+                write!(f, "file was saved with seed {}, world seed is {}", found, expected)
+            }
+            RegionError::PositionMismatch { expected, found } => {
+                write!(f, "expected position {:?}, found {:?}", expected, found)
+            }
+            RegionError::BadRecord(reason) => write!(f, "bad chunk record: {}", reason),
+        }
+    }
+}
+
+
+impl std::error::Error for RegionError {}
+
+
+impl From<io::Error> for RegionError {
+    fn from(err: io::Error) -> Self {
+        RegionError::Io(err)
+    }
+}
+
+
+/// Последовательное чтение little-endian значений с проверкой длины
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn bytes(&mut self, count: usize) -> Result<&'a [u8], RegionError> {
+        let end = self.pos + count;
+        if end > self.data.len() {
+            return Err(RegionError::Truncated { needed: end, len: self.data.len() });
+        }
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, RegionError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, RegionError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, RegionError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, RegionError> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, RegionError> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+}
+
+
+/// Общее начало файлов региона и мира: magic, версия формата и сид
+fn write_header(out: &mut Vec<u8>, magic: &[u8; 4], seed: u32) {
+    out.extend_from_slice(magic);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&seed.to_le_bytes());
+}
+
+
+fn read_header(reader: &mut ByteReader, magic: &[u8; 4], seed: u32) -> Result<(), RegionError> {
+    if reader.bytes(4)? != magic {
+        return Err(RegionError::BadMagic);
+    }
+    let version = reader.u32()?;
+    if version != FORMAT_VERSION {
+        return Err(RegionError::UnknownVersion(version));
+    }
+    let found = reader.u32()?;
+    if found != seed {
+        return Err(RegionError::SeedMismatch { expected: seed, found });
+    }
+    Ok(())
+}
+
+
+/// Файл мира: заголовок как у региона и время суток
+fn encode_world(seed: u32, time: f32) -> Vec<u8> {
+    let mut out = Vec::with_capacity(WORLD_SIZE);
+    write_header(&mut out, WORLD_MAGIC, seed);
+    out.extend_from_slice(&time.to_le_bytes());
+    out
+}
+
+
+fn decode_world(data: &[u8], seed: u32) -> Result<f32, RegionError> {
+    let mut reader = ByteReader::new(data);
+    read_header(&mut reader, WORLD_MAGIC, seed)?;
+    let time = reader.f32()?;
+    if data.len() != WORLD_SIZE {
+        return Err(RegionError::SizeMismatch { expected: WORLD_SIZE, len: data.len() });
+    }
+    Ok(time)
+}
+
+
+/// Регион: REGION_SIZE³ чанков в одном файле.
+/// Записи чанков хранятся как есть, их содержимое разбирает `Chunk`.
+pub struct Region {
+    pub x: isize,
+    pub y: isize,
+    pub z: isize,
+    records: Vec<Option<Vec<u8>>>,
+}
+
+
+impl Region {
+    pub fn new(x: isize, y: isize, z: isize) -> Self {
+        Self { x, y, z, records: vec![None; REGION_VOL] }
+    }
+
+    fn index(cx: isize, cy: isize, cz: isize) -> usize {
+        let lx = cx.rem_euclid(REGION_SIZE);
+        let ly = cy.rem_euclid(REGION_SIZE);
+        let lz = cz.rem_euclid(REGION_SIZE);
+        ((ly * REGION_SIZE + lz) * REGION_SIZE + lx) as usize
+    }
+
+    pub fn get(&self, cx: isize, cy: isize, cz: isize) -> Option<&[u8]> {
+        self.records[Self::index(cx, cy, cz)].as_deref()
+    }
+
+    pub fn set(&mut self, cx: isize, cy: isize, cz: isize, record: Vec<u8>) {
+        self.records[Self::index(cx, cy, cz)] = Some(record);
+    }
+
+    /// Заголовок, таблица смещений и записи вида [длина u32][cx, cy, cz i32][данные]
+    pub fn encode(&self, seed: u32) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_SIZE + INDEX_SIZE);
+        write_header(&mut out, MAGIC, seed);
+        for coord in [self.x, self.y, self.z] {
+            out.extend_from_slice(&(coord as i32).to_le_bytes());
+        }
+
+        let mut body = Vec::new();
+        let mut index = Vec::with_capacity(INDEX_SIZE);
+        for (i, record) in self.records.iter().enumerate() {
+            match record {
+                Some(record) => {
+                    let (cx, cy, cz) = self.chunk_at(i);
+                    let offset = HEADER_SIZE + INDEX_SIZE + body.len();
+                    let length = 12 + record.len();
+                    index.extend_from_slice(&(offset as u32).to_le_bytes());
+                    index.extend_from_slice(&(length as u32).to_le_bytes());
+
+                    body.extend_from_slice(&(length as u32).to_le_bytes());
+                    for coord in [cx, cy, cz] {
+                        body.extend_from_slice(&(coord as i32).to_le_bytes());
+                    }
+                    body.extend_from_slice(record);
+                }
+                None => index.extend_from_slice(&[0; 8]),
+            }
+        }
+
+        out.extend_from_slice(&index);
+        out.extend_from_slice(&body);
+        out
+    }
+
+    pub fn decode(data: &[u8], seed: u32, x: isize, y: isize, z: isize) -> Result<Self, RegionError> {
+        let mut reader = ByteReader::new(data);
+        read_header(&mut reader, MAGIC, seed)?;
+        let found = (reader.i32()? as isize, reader.i32()? as isize, reader.i32()? as isize);
+        if found != (x, y, z) {
+            return Err(RegionError::PositionMismatch { expected: (x, y, z), found });
+        }
+
+        let mut region = Region::new(x, y, z);
+        for i in 0..REGION_VOL {
+            let offset = reader.u32()? as usize;
+            let length = reader.u32()? as usize;
+            if length == 0 {
+                continue;
+            }
+
+            let mut record = ByteReader::new(data);
+            record.bytes(offset)?;
+            if record.u32()? as usize != length {
+                return Err(RegionError::BadRecord(format!("length prefix of record {} does not match index", i)));
+            }
+            if length < 12 {
+                return Err(RegionError::BadRecord(format!("record {} is too short", i)));
+            }
+            let expected = region.chunk_at(i);
+            let found = (record.i32()? as isize, record.i32()? as isize, record.i32()? as isize);
+            if found != expected {
+                return Err(RegionError::PositionMismatch { expected, found });
+            }
+            region.records[i] = Some(record.bytes(length - 12)?.to_vec());
+        }
+        Ok(region)
+    }
+
+    fn chunk_at(&self, index: usize) -> (isize, isize, isize) {
+        let index = index as isize;
+        let lx = index % REGION_SIZE;
+        let lz = (index / REGION_SIZE) % REGION_SIZE;
+        let ly = index / (REGION_SIZE * REGION_SIZE);
+        (self.x * REGION_SIZE + lx, self.y * REGION_SIZE + ly, self.z * REGION_SIZE + lz)
+    }
+}
+
+
+/// Папка мира: лениво читает регионы с диска и пишет обратно изменённые
+pub struct RegionStorage {
+    dir: String,
+    seed: u32,
+    regions: HashMap<(isize, isize, isize), Region>,
+    dirty: HashSet<(isize, isize, isize)>,
+}
+
+
+impl RegionStorage {
+    pub fn new(dir: &str, seed: u32) -> Self {
+        Self {
+            dir: dir.to_string(),
+            seed,
+            regions: HashMap::new(),
+            dirty: HashSet::new(),
+        }
+    }
+
+    pub fn region_pos(cx: isize, cy: isize, cz: isize) -> (isize, isize, isize) {
+        (cx.div_euclid(REGION_SIZE), cy.div_euclid(REGION_SIZE), cz.div_euclid(REGION_SIZE))
+    }
+
+    fn path(&self, (x, y, z): (isize, isize, isize)) -> String {
+        format!("{}/r.{}.{}.{}.bin", self.dir, x, y, z)
+    }
+
+    fn region(&mut self, key: (isize, isize, isize)) -> Result<&mut Region, RegionError> {
+        if !self.regions.contains_key(&key) {
+            let region = match read_whole_file(&self.path(key)) {
+                Ok(data) => Region::decode(&data, self.seed, key.0, key.1, key.2)?,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Region::new(key.0, key.1, key.2),
+                Err(err) => return Err(err.into()),
+            };
+            self.regions.insert(key, region);
+        }
+        Ok(self.regions.get_mut(&key).unwrap())
+    }
+
+    /// Сохранённая запись чанка, если она есть
+    pub fn load(&mut self, cx: isize, cy: isize, cz: isize) -> Result<Option<&[u8]>, RegionError> {
+        let region = self.region(Self::region_pos(cx, cy, cz))?;
+        Ok(region.get(cx, cy, cz))
+    }
+
+    pub fn store(&mut self, cx: isize, cy: isize, cz: isize, record: Vec<u8>) -> Result<(), RegionError> {
+        let key = Self::region_pos(cx, cy, cz);
+        self.region(key)?.set(cx, cy, cz, record);
+        self.dirty.insert(key);
+        Ok(())
+    }
+
+    fn write(&mut self, key: (isize, isize, isize)) -> Result<(), RegionError> {
+        std::fs::create_dir_all(&self.dir)?;
+        let data = self.regions[&key].encode(self.seed);
+        write_binary_file(&self.path(key), &data)?;
+        self.dirty.remove(&key);
+        Ok(())
+    }
+
+    /// Записывает изменённые регионы на диск, возвращает их количество
+    pub fn flush(&mut self) -> Result<usize, RegionError> {
+        let dirty: Vec<_> = self.dirty.iter().copied().collect();
+        for &key in &dirty {
+            self.write(key)?;
+        }
+        Ok(dirty.len())
+    }
+
+    /// Сохранённое время суток мира, None - мир ещё не сохранялся
+    pub fn load_time(&self) -> Result<Option<f32>, RegionError> {
+        match read_whole_file(&self.world_path()) {
+            Ok(data) => Ok(Some(decode_world(&data, self.seed)?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save_time(&self, time: f32) -> Result<(), RegionError> {
+        std::fs::create_dir_all(&self.dir)?;
+        write_binary_file(&self.world_path(), &encode_world(self.seed, time))?;
+        Ok(())
+    }
+
+    fn world_path(&self) -> String {
+        format!("{}/world.bin", self.dir)
+    }
+
+    /// Выгружает из памяти регионы, для которых `keep` вернул false, сначала записав изменённые
+    pub fn retain(&mut self, mut keep: impl FnMut(&(isize, isize, isize)) -> bool) -> Result<(), RegionError> {
+        let dropped: Vec<_> = self.regions.keys().filter(|key| !keep(key)).copied().collect();
+        for key in dropped {
+            if self.dirty.contains(&key) {
+                self.write(key)?;
+            }
+            self.regions.remove(&key);
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED: u32 = 7;
+
+    fn region() -> Region {
+        let mut region = Region::new(1, -1, 0);
+        region.set(8, -8, 0, vec![1, 2, 3]);
+        region.set(15, -1, 7, vec![4; 40]);
+        region
+    }
+
+    #[test]
+    fn region_round_trips() {
+        let decoded = Region::decode(&region().encode(SEED), SEED, 1, -1, 0).unwrap();
+        assert_eq!(decoded.get(8, -8, 0), Some(&[1, 2, 3][..]));
+        assert_eq!(decoded.get(15, -1, 7), Some(&[4; 40][..]));
+        assert_eq!(decoded.get(9, -8, 0), None);
+    }
+
+    #[test]
+    fn corrupted_regions_are_rejected() {
+        let data = region().encode(SEED);
+        let decode = |data: &[u8]| Region::decode(data, SEED, 1, -1, 0).err().unwrap();
+
+        assert!(matches!(decode(&data[..data.len() - 1]), RegionError::Truncated { .. }));
+        assert!(matches!(decode(&data[..10]), RegionError::Truncated { needed: 12, len: 10 }));
+
+        let mut bad = data.clone();
+        bad[0] = b'X';
+        assert!(matches!(decode(&bad), RegionError::BadMagic));
+
+        let mut bad = data.clone();
+        bad[4..8].copy_from_slice(&2u32.to_le_bytes());
+        assert!(matches!(decode(&bad), RegionError::UnknownVersion(2)));
+
+        assert!(matches!(
+            Region::decode(&data, SEED + 1, 1, -1, 0),
+            Err(RegionError::SeedMismatch { expected: 8, found: 7 })
+        ));
+        assert!(matches!(
+            Region::decode(&data, SEED, 0, 0, 0),
+            Err(RegionError::PositionMismatch { expected: (0, 0, 0), found: (1, -1, 0) })
+        ));
+
+        // первая запись: длина и координаты чанка сразу за таблицей смещений
+        let record = HEADER_SIZE + INDEX_SIZE;
+        let mut bad = data.clone();
+        bad[record..record + 4].copy_from_slice(&99u32.to_le_bytes());
+        assert!(matches!(decode(&bad), RegionError::BadRecord(_)));
+
+        let mut bad = data;
+        bad[record + 4..record + 8].copy_from_slice(&9i32.to_le_bytes());
+        assert!(matches!(decode(&bad), RegionError::PositionMismatch { found: (9, -8, 0), .. }));
+    }
+
+    #[test]
+    fn unused_regions_are_written_and_dropped() {
+        let dir = "target/test-regions-retain";
+        let _ = std::fs::remove_dir_all(dir);
+        let mut storage = RegionStorage::new(dir, SEED);
+        storage.store(0, 0, 0, vec![1]).unwrap();
+        storage.store(8, 0, 0, vec![2]).unwrap();
+        assert_eq!(storage.regions.len(), 2);
+
+        storage.retain(|&key| key == (0, 0, 0)).unwrap();
+        assert_eq!(storage.regions.len(), 1);
+        // выгруженный регион записан на диск и читается заново
+        assert_eq!(storage.load(8, 0, 0).unwrap(), Some(&[2][..]));
+        assert_eq!(storage.regions.len(), 2);
+    }
+
+    #[test]
+    fn world_time_is_saved() {
+        let dir = "target/test-world-time";
+        let _ = std::fs::remove_dir_all(dir);
+        let storage = RegionStorage::new(dir, SEED);
+        assert!(matches!(storage.load_time(), Ok(None)));
+        storage.save_time(0.6).unwrap();
+        assert!(matches!(storage.load_time(), Ok(Some(time)) if time == 0.6));
+
+        // мир с другим сидом не подхватывает чужое время
+        let other = RegionStorage::new(dir, SEED + 1);
+        assert!(matches!(other.load_time(), Err(RegionError::SeedMismatch { expected: 8, found: 7 })));
+
+        let data = encode_world(SEED, 0.6);
+        assert!(matches!(decode_world(&data[..14], SEED), Err(RegionError::Truncated { needed: 16, len: 14 })));
+        let mut bad = data.clone();
+        bad.push(0);
+        assert!(matches!(decode_world(&bad, SEED), Err(RegionError::SizeMismatch { expected: 16, len: 17 })));
+        let mut bad = data.clone();
+        bad[0] = b'X';
+        assert!(matches!(decode_world(&bad, SEED), Err(RegionError::BadMagic)));
+        // файл региона не принимается за файл мира
+        std::fs::write(storage.world_path(), region().encode(SEED)).unwrap();
+        assert!(matches!(storage.load_time(), Err(RegionError::BadMagic)));
+    }
+}
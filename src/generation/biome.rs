@@ -0,0 +1,97 @@
+use crate::generation::GeneratorBlocks;
+
+
+/// Набор блоков столба земли: верхний блок, слой под ним и всё, что глубже
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Biome {
+    pub name: &'static str,
+    pub surface: u16,
+    pub subsurface: u16,
+    pub filler: u16,
+    /// Толщина слоя `subsurface` под верхним блоком
+    pub subsurface_depth: isize,
+    /// Вероятность дерева и валуна на одну попытку украшения
+    pub trees: f64,
+    pub boulders: f64,
+}
+
+
+impl Biome {
+    /// Блок на глубине `depth` под поверхностью (0 - сам верхний блок)
+    pub fn block_at(&self, depth: isize) -> u16 {
+        if depth == 0 {
+            self.surface
+        } else if depth <= self.subsurface_depth {
+            self.subsurface
+        } else {
+            self.filler
+        }
+    }
+}
+
+
+/// Биомы генератора рельефа с id блоков из res/blocks.txt
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Biomes {
+    pub plains: Biome,
+    pub desert: Biome,
+    pub tundra: Biome,
+    pub rocks: Biome,
+}
+
+
+impl Biomes {
+    pub fn new(ids: &GeneratorBlocks) -> Self {
+        Self {
+            plains: Biome {
+                name: "plains",
+                surface: ids.grass,
+                subsurface: ids.dirt,
+                filler: ids.stone,
+                subsurface_depth: 3,
+                trees: 0.25,
+                boulders: 0.02,
+            },
+            desert: Biome {
+                name: "desert",
+                surface: ids.sand,
+                subsurface: ids.sand,
+                filler: ids.stone,
+                subsurface_depth: 4,
+                trees: 0.0,
+                boulders: 0.05,
+            },
+            tundra: Biome {
+                name: "tundra",
+                surface: ids.snow,
+                subsurface: ids.dirt,
+                filler: ids.stone,
+                subsurface_depth: 2,
+                trees: 0.1,
+                boulders: 0.05,
+            },
+            rocks: Biome {
+                name: "rocks",
+                surface: ids.stone,
+                subsurface: ids.stone,
+                filler: ids.stone,
+                subsurface_depth: 0,
+                trees: 0.02,
+                boulders: 0.3,
+            },
+        }
+    }
+
+    /// Биом по климату. Температура и влажность лежат примерно в -1..1
+    pub fn select(&self, temperature: f64, humidity: f64) -> Biome {
+        if temperature < -0.35 {
+            self.tundra
+        } else if temperature > 0.3 && humidity < 0.0 {
+            self.desert
+        } else if humidity < -0.45 {
+            self.rocks
+        } else {
+            self.plains
+        }
+    }
+}
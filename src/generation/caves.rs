@@ -0,0 +1,65 @@
+use noise::{NoiseFn, OpenSimplex};
+
+use crate::voxels::chunk::{Chunk, CHUNK_D, CHUNK_H, CHUNK_W};
+use crate::voxels::voxel::Voxel;
+
+
+const TUNNEL_SCALE: f64 = 0.018;
+/// Ширина туннеля: насколько близко к нулю должны быть оба шума
+const TUNNEL_WIDTH: f64 = 0.07;
+const CAVERN_SCALE: f64 = 0.03;
+const CAVERN_THRESHOLD: f64 = 0.55;
+/// Пещеры-залы начинаются не ближе этой глубины к поверхности
+const CAVERN_DEPTH: isize = 10;
+
+
+/// Вырезает в земле туннели и залы. Решение принимается по мировым координатам вокселя,
+/// так что пещеры продолжаются через границы чанков
+pub struct CaveCarver {
+    tunnel_a: OpenSimplex,
+    tunnel_b: OpenSimplex,
+    caverns: OpenSimplex,
+}
+
+
+impl CaveCarver {
+    pub fn new(seed: u32) -> Self {
+        Self {
+            tunnel_a: OpenSimplex::new(seed.wrapping_add(3)),
+            tunnel_b: OpenSimplex::new(seed.wrapping_add(4)),
+            caverns: OpenSimplex::new(seed.wrapping_add(5)),
+        }
+    }
+
+    pub fn is_cave(&self, x: isize, y: isize, z: isize, surface: isize) -> bool {
+        // туннели сплюснуты по высоте, чтобы чаще шли вдоль земли
+        let point = [x as f64 * TUNNEL_SCALE, y as f64 * TUNNEL_SCALE * 1.6, z as f64 * TUNNEL_SCALE];
+        if self.tunnel_a.get(point).abs() < TUNNEL_WIDTH && self.tunnel_b.get(point).abs() < TUNNEL_WIDTH {
+            return true;
+        }
+        if surface - y < CAVERN_DEPTH {
+            return false;
+        }
+        let point = [x as f64 * CAVERN_SCALE, y as f64 * CAVERN_SCALE * 2.0, z as f64 * CAVERN_SCALE];
+        self.caverns.get(point) > CAVERN_THRESHOLD
+    }
+
+    /// `heights` - высоты поверхности столбов чанка в порядке z * CHUNK_W + x
+    pub fn carve(&self, cx: isize, cy: isize, cz: isize, heights: &[isize], voxels: &mut [Voxel]) {
+        for z in 0..CHUNK_D {
+            for x in 0..CHUNK_W {
+                let surface = heights[(z * CHUNK_W + x) as usize];
+                let (real_x, real_z) = (x + cx * CHUNK_W, z + cz * CHUNK_D);
+                for y in 0..CHUNK_H {
+                    let real_y = y + cy * CHUNK_H;
+                    if real_y > surface {
+                        break;
+                    }
+                    if self.is_cave(real_x, real_y, real_z, surface) {
+                        voxels[Chunk::index(x as usize, y as usize, z as usize)] = Voxel::default();
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::generation::random::ChunkRng;
+use crate::generation::GeneratorBlocks;
+use crate::voxels::voxel::Voxel;
+
+
+/// Запись украшения: мировые координаты вокселя и что туда поставить
+pub type Placement = ((isize, isize, isize), Voxel);
+
+
+/// Приоритет блока при наложении украшений: воздух < листва < всё остальное
+fn rank(voxel: Voxel, ids: &GeneratorBlocks) -> u8 {
+    match voxel.id {
+        0 => 0,
+        id if id == ids.leaves => 1,
+        _ => 2,
+    }
+}
+
+
+/// Заменяет ли украшение то, что уже стоит в вокселе. Блоки с равным приоритетом сравниваются
+/// по id и состоянию, так что из любых двух записей побеждает одна и та же,
+/// и итог одинаков, какой бы чанк ни сгенерировался первым
+pub fn replaces(current: Voxel, new: Voxel, ids: &GeneratorBlocks) -> bool {
+    (rank(new, ids), new.id, new.state) > (rank(current, ids), current.id, current.state)
+}
+
+
+/// Дерево: ствол из брёвен от (x, y, z) вверх и шапка листвы
+pub fn tree(x: isize, y: isize, z: isize, ids: &GeneratorBlocks, rng: &mut ChunkRng, out: &mut Vec<Placement>) {
+    let height = 4 + rng.below(3) as isize;
+    let top = y + height;
+    for dy in -2..=1isize {
+        let radius: isize = if dy >= 0 { 1 } else { 2 };
+        for dz in -radius..=radius {
+            for dx in -radius..=radius {
+                // срезаем углы, чтобы крона была круглее
+                if radius == 2 && dx.abs() == 2 && dz.abs() == 2 && rng.below(2) == 0 {
+                    continue;
+                }
+                out.push(((x + dx, top + dy, z + dz), Voxel { id: ids.leaves, state: 0 }));
+            }
+        }
+    }
+    out.push(((x, top + 2, z), Voxel { id: ids.leaves, state: 0 }));
+    for dy in 0..height {
+        out.push(((x, y + dy, z), Voxel { id: ids.log, state: 0 }));
+    }
+}
+
+
+/// Валун: неровный каменный шар, наполовину утопленный в землю
+pub fn boulder(x: isize, y: isize, z: isize, ids: &GeneratorBlocks, rng: &mut ChunkRng, out: &mut Vec<Placement>) {
+    let radius = 1 + rng.below(2) as isize;
+    for dy in -radius..=radius {
+        for dz in -radius..=radius {
+            for dx in -radius..=radius {
+                let distance = dx * dx + dy * dy + dz * dz;
+                if distance <= radius * radius && (distance < radius * radius || rng.below(3) != 0) {
+                    out.push(((x + dx, y + dy, z + dz), Voxel { id: ids.stone, state: 0 }));
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlapping_placements_do_not_depend_on_order() {
+        let ids = GeneratorBlocks { stone: 1, grass: 2, dirt: 6, sand: 7, snow: 8, log: 11, leaves: 12 };
+        let stone = Voxel { id: ids.stone, state: 0 };
+        let log = Voxel { id: ids.log, state: 0 };
+        let tilted_log = Voxel { id: ids.log, state: 1 };
+        let leaves = Voxel { id: ids.leaves, state: 0 };
+
+        let apply = |writes: &[Voxel]| writes.iter().fold(Voxel::default(), |current, &new| {
+            if replaces(current, new, &ids) { new } else { current }
+        });
+        for (first, second) in [(stone, log), (log, tilted_log), (leaves, stone)] {
+            assert_eq!(apply(&[first, second]), apply(&[second, first]));
+        }
+        assert_eq!(apply(&[log, leaves]), log);
+        assert!(!replaces(stone, stone, &ids));
+    }
+}
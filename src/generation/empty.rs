@@ -0,0 +1,11 @@
+use crate::generation::WorldGenerator;
+use crate::voxels::voxel::Voxel;
+
+
+/// Мир из одного воздуха
+pub struct EmptyGenerator;
+
+
+impl WorldGenerator for EmptyGenerator {
+    fn generate(&self, _cx: isize, _cy: isize, _cz: isize, _voxels: &mut [Voxel]) {}
+}
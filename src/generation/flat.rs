@@ -0,0 +1,40 @@
+use crate::generation::{GeneratorBlocks, WorldGenerator};
+use crate::voxels::chunk::{Chunk, CHUNK_D, CHUNK_H, CHUNK_W};
+use crate::voxels::voxel::Voxel;
+
+
+/// Камень до `height`, на нём слой травы
+pub struct FlatGenerator {
+    pub height: isize,
+    ids: GeneratorBlocks,
+}
+
+
+impl FlatGenerator {
+    pub fn new(height: isize, ids: GeneratorBlocks) -> Self {
+        Self { height, ids }
+    }
+}
+
+
+impl WorldGenerator for FlatGenerator {
+    fn generate(&self, _cx: isize, cy: isize, _cz: isize, voxels: &mut [Voxel]) {
+        for y in 0..CHUNK_H {
+            let real_y = y + cy * CHUNK_H;
+            let id = match real_y {
+                _ if real_y < self.height => self.ids.stone,
+                _ if real_y == self.height => self.ids.grass,
+                _ => break,
+            };
+            for z in 0..CHUNK_D {
+                for x in 0..CHUNK_W {
+                    voxels[Chunk::index(x as usize, y as usize, z as usize)].id = id;
+                }
+            }
+        }
+    }
+
+    fn surface_height(&self, _x: isize, _z: isize) -> Option<isize> {
+        Some(self.height)
+    }
+}
@@ -0,0 +1,214 @@
+pub mod biome;
+pub mod caves;
+pub mod decorations;
+pub mod empty;
+pub mod flat;
+pub mod noise_generator;
+pub mod ores;
+pub mod random;
+pub mod terrain;
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::settings::WorldSettings;
+use crate::voxels::block::Blocks;
+use crate::voxels::voxel::Voxel;
+
+pub use decorations::Placement;
+pub use empty::EmptyGenerator;
+pub use flat::FlatGenerator;
+pub use noise_generator::NoiseGenerator;
+pub use ores::OreConfig;
+pub use terrain::TerrainGenerator;
+
+
+/// Генератору нужен блок, которого нет в res/blocks.txt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingBlock(pub String);
+
+
+impl fmt::Display for MissingBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "world generator needs block '{}', which is not defined", self.0)
+    }
+}
+
+
+impl std::error::Error for MissingBlock {}
+
+
+/// id блока по имени из res/blocks.txt
+pub fn block_id(blocks: &Blocks, name: &str) -> Result<u16, MissingBlock> {
+    blocks.by_name(name).map(|block| block.id).ok_or_else(|| MissingBlock(name.to_string()))
+}
+
+
+/// id блоков, которыми пользуются генераторы. Берутся по именам при создании генератора,
+/// так что генерация не зависит от порядка блоков в res/blocks.txt
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GeneratorBlocks {
+    pub stone: u16,
+    pub grass: u16,
+    pub dirt: u16,
+    pub sand: u16,
+    pub snow: u16,
+    pub log: u16,
+    pub leaves: u16,
+}
+
+
+impl GeneratorBlocks {
+    pub fn resolve(blocks: &Blocks) -> Result<Self, MissingBlock> {
+        Ok(Self {
+            stone: block_id(blocks, "stone")?,
+            grass: block_id(blocks, "grass")?,
+            dirt: block_id(blocks, "dirt")?,
+            sand: block_id(blocks, "sand")?,
+            snow: block_id(blocks, "snow")?,
+            log: block_id(blocks, "log")?,
+            leaves: block_id(blocks, "leaves")?,
+        })
+    }
+}
+
+
+/// Заполняет чанки мира. Результат зависит только от сида и координат чанка,
+/// поэтому генератор можно звать из любого потока и в любом порядке
+pub trait WorldGenerator: Send + Sync {
+    /// `voxels` - CHUNK_VOL вокселей чанка (cx, cy, cz) в порядке `Chunk::index`, изначально воздух
+    fn generate(&self, cx: isize, cy: isize, cz: isize, voxels: &mut [Voxel]);
+
+    /// Верхний блок столба, если генератор его знает. Освещение считает,
+    /// что ниже этой высоты неба не видно, пока чанк сверху не загружен
+    fn surface_height(&self, _x: isize, _z: isize) -> Option<isize> {
+        None
+    }
+
+    /// Украшения (деревья, валуны), которые начинаются в этом чанке.
+    /// Они могут заходить в соседние чанки, но не дальше: каждый чанк при генерации
+    /// собирает записи своих украшений и украшений соседей
+    fn decorate(&self, _cx: isize, _cy: isize, _cz: isize) -> Vec<Placement> {
+        Vec::new()
+    }
+
+    /// Заменяет ли запись украшения то, что уже стоит в вокселе
+    fn replaces(&self, current: Voxel, _new: Voxel) -> bool {
+        current.id == 0
+    }
+}
+
+
+/// Какой генератор использует мир
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GeneratorKind {
+    Empty,
+    Flat,
+    Noise,
+    Terrain,
+}
+
+
+impl GeneratorKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "empty" => Some(GeneratorKind::Empty),
+            "flat" => Some(GeneratorKind::Flat),
+            "noise" => Some(GeneratorKind::Noise),
+            "terrain" => Some(GeneratorKind::Terrain),
+            _ => None,
+        }
+    }
+
+    /// Генератор с id блоков из `blocks`
+    pub fn create(self, settings: &WorldSettings, blocks: &Blocks) -> Result<Arc<dyn WorldGenerator>, MissingBlock> {
+        let seed = settings.seed;
+        let ids = GeneratorBlocks::resolve(blocks)?;
+        Ok(match self {
+            GeneratorKind::Empty => Arc::new(EmptyGenerator),
+            GeneratorKind::Flat => Arc::new(FlatGenerator::new(2, ids)),
+            GeneratorKind::Noise => Arc::new(NoiseGenerator::new(seed, ids)),
+            GeneratorKind::Terrain => Arc::new(TerrainGenerator::new(seed, blocks, &settings.ores)?),
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{blocks, settings};
+    use crate::voxels::chunk::CHUNK_VOL;
+
+    fn generate(generator: &dyn WorldGenerator, cx: isize, cy: isize, cz: isize) -> Vec<Voxel> {
+        let mut voxels = vec![Voxel::default(); CHUNK_VOL];
+        generator.generate(cx, cy, cz, &mut voxels);
+        voxels
+    }
+
+    #[test]
+    fn same_seed_gives_same_chunks() {
+        let blocks = blocks();
+        for kind in [GeneratorKind::Empty, GeneratorKind::Flat, GeneratorKind::Noise, GeneratorKind::Terrain] {
+            let first = kind.create(&settings(42, kind), &blocks).unwrap();
+            let second = kind.create(&settings(42, kind), &blocks).unwrap();
+            for pos in [(0, 0, 0), (-3, 1, 7), (5, -2, -1)] {
+                assert_eq!(generate(&*first, pos.0, pos.1, pos.2), generate(&*second, pos.0, pos.1, pos.2));
+            }
+        }
+    }
+
+    #[test]
+    fn seed_changes_noise_terrain() {
+        let blocks = blocks();
+        let first = GeneratorKind::Noise.create(&settings(1, GeneratorKind::Noise), &blocks).unwrap();
+        let second = GeneratorKind::Noise.create(&settings(2, GeneratorKind::Noise), &blocks).unwrap();
+        assert_ne!(generate(&*first, 0, 1, 0), generate(&*second, 0, 1, 0));
+    }
+
+    #[test]
+    fn terrain_columns_match_heightmap_across_chunks() {
+        use crate::voxels::chunk::{Chunk, CHUNK_H};
+
+        let generator = TerrainGenerator::new(7, &blocks(), &[]).unwrap();
+        let chunks: Vec<Vec<Voxel>> = (-3..3).map(|cy| generate(&generator, 1, cy, -2)).collect();
+        for (x, z) in [(0, 0), (15, 15), (7, 3)] {
+            let height = generator.height_at(16 + x as isize, -32 + z as isize);
+            let biome = generator.biome(16 + x as isize, -32 + z as isize);
+            for (i, voxels) in chunks.iter().enumerate() {
+                for y in 0..CHUNK_H as usize {
+                    let real_y = (i as isize - 3) * CHUNK_H + y as isize;
+                    let id = voxels[Chunk::index(x, y, z)].id;
+                    if real_y > height {
+                        assert_eq!(id, 0);
+                    } else {
+                        // ниже поверхности может оказаться пещера
+                        assert!(id == 0 || id == biome.block_at(height - real_y));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ores_stay_in_their_depth_range() {
+        let blocks = blocks();
+        let coal = block_id(&blocks, "coal_ore").unwrap();
+        let ore = OreConfig { block: "coal_ore".to_string(), min_y: -32, max_y: -17, veins_per_chunk: 20.0, vein_size: 8 };
+        let generator = TerrainGenerator::new(3, &blocks, &[ore]).unwrap();
+        let count = |cy| generate(&generator, 0, cy, 0).iter().filter(|voxel| voxel.id == coal).count();
+        assert!(count(-2) > 0);
+        assert_eq!(count(-1), 0);
+        assert_eq!(count(-3), 0);
+    }
+
+    #[test]
+    fn missing_generator_block_is_reported() {
+        let mut blocks = Blocks::init();
+        for (id, name) in ["air", "stone", "grass", "dirt", "sand", "snow", "log"].into_iter().enumerate() {
+            blocks.add(crate::voxels::Block::new(id as u16, name, 0));
+        }
+        let settings = settings(1, GeneratorKind::Flat);
+        assert_eq!(GeneratorKind::Flat.create(&settings, &blocks).err(), Some(MissingBlock("leaves".to_string())));
+    }
+}
@@ -0,0 +1,40 @@
+use noise::{NoiseFn, OpenSimplex};
+
+use crate::generation::{GeneratorBlocks, WorldGenerator};
+use crate::voxels::chunk::{Chunk, CHUNK_D, CHUNK_H, CHUNK_W};
+use crate::voxels::voxel::Voxel;
+
+
+/// Камень там, где 3D шум выше порога, и сплошная трава до y = 2
+pub struct NoiseGenerator {
+    noise: OpenSimplex,
+    ids: GeneratorBlocks,
+}
+
+
+impl NoiseGenerator {
+    pub fn new(seed: u32, ids: GeneratorBlocks) -> Self {
+        Self { noise: OpenSimplex::new(seed), ids }
+    }
+}
+
+
+impl WorldGenerator for NoiseGenerator {
+    fn generate(&self, cx: isize, cy: isize, cz: isize, voxels: &mut [Voxel]) {
+        for z in 0..CHUNK_D {
+            for x in 0..CHUNK_W {
+                let real_x = x + cx * CHUNK_W;
+                let real_z = z + cz * CHUNK_D;
+                for y in 0..CHUNK_H {
+                    let real_y = y + cy * CHUNK_H;
+                    let index = Chunk::index(x as usize, y as usize, z as usize);
+                    if real_y <= 2 {
+                        voxels[index].id = self.ids.grass;
+                    } else if self.noise.get([real_x as f64 * 0.0125, real_y as f64 * 0.0125, real_z as f64 * 0.0125]) > 0.1 {
+                        voxels[index].id = self.ids.stone;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::generation::random::ChunkRng;
+use crate::generation::{block_id, MissingBlock};
+use crate::voxels::block::Blocks;
+use crate::voxels::chunk::{Chunk, CHUNK_D, CHUNK_H, CHUNK_W};
+use crate::voxels::voxel::Voxel;
+
+
+/// Описание одной руды для `OrePass`
+#[derive(Clone, Debug, PartialEq)]
+pub struct OreConfig {
+    /// Имя блока руды из res/blocks.txt
+    pub block: String,
+    /// Мировые высоты, на которых может начинаться жила, включительно
+    pub min_y: isize,
+    pub max_y: isize,
+    /// Среднее число жил на чанк, дробная часть - вероятность ещё одной
+    pub veins_per_chunk: f64,
+    /// Сколько шагов делает жила
+    pub vein_size: usize,
+}
+
+
+/// Расставляет жилы руд внутри чанка, заменяя только камень.
+/// Жилы не выходят за границы чанка, поэтому соседи генерируются независимо
+pub struct OrePass {
+    seed: u32,
+    stone: u16,
+    /// id блока руды и её описание
+    ores: Vec<(u16, OreConfig)>,
+}
+
+
+impl OrePass {
+    pub fn new(seed: u32, stone: u16, ores: &[OreConfig], blocks: &Blocks) -> Result<Self, MissingBlock> {
+        let ores = ores.iter()
+            .map(|ore| Ok((block_id(blocks, &ore.block)?, ore.clone())))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { seed, stone, ores })
+    }
+
+    pub fn apply(&self, cx: isize, cy: isize, cz: isize, voxels: &mut [Voxel]) {
+        for (i, (block, ore)) in self.ores.iter().enumerate() {
+            let mut rng = ChunkRng::new(self.seed, cx, cy, cz, i as u64);
+            let mut veins = ore.veins_per_chunk.floor() as usize;
+            if rng.next_f64() < ore.veins_per_chunk.fract() {
+                veins += 1;
+            }
+
+            for _ in 0..veins {
+                let mut x = rng.below(CHUNK_W as usize) as isize;
+                let mut y = rng.below(CHUNK_H as usize) as isize;
+                let mut z = rng.below(CHUNK_D as usize) as isize;
+                let real_y = y + cy * CHUNK_H;
+                if real_y < ore.min_y || real_y > ore.max_y {
+                    continue;
+                }
+
+                for _ in 0..ore.vein_size {
+                    if (0..CHUNK_W).contains(&x) && (0..CHUNK_H).contains(&y) && (0..CHUNK_D).contains(&z) {
+                        let voxel = &mut voxels[Chunk::index(x as usize, y as usize, z as usize)];
+                        if voxel.id == self.stone {
+                            voxel.id = *block;
+                        }
+                    }
+                    match rng.below(6) {
+                        0 => x -= 1,
+                        1 => x += 1,
+                        2 => y -= 1,
+                        3 => y += 1,
+                        4 => z -= 1,
+                        _ => z += 1,
+                    }
+                }
+            }
+        }
+    }
+}
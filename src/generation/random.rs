@@ -0,0 +1,39 @@
+/// Маленький детерминированный генератор (splitmix64) для стадий генерации.
+/// Сид выводится из сида мира и координат, поэтому результат не зависит от порядка генерации чанков
+pub struct ChunkRng {
+    state: u64,
+}
+
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+
+impl ChunkRng {
+    /// `salt` разводит потоки разных стадий для одного и того же чанка
+    pub fn new(seed: u32, cx: isize, cy: isize, cz: isize, salt: u64) -> Self {
+        let mut state = mix(seed as u64 ^ 0x9e3779b97f4a7c15);
+        for value in [cx as u64, cy as u64, cz as u64, salt] {
+            state = mix(state ^ value);
+        }
+        Self { state }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        mix(self.state)
+    }
+
+    /// Число в 0..1
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Число в 0..bound
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
@@ -0,0 +1,138 @@
+use noise::{NoiseFn, OpenSimplex};
+
+use crate::generation::biome::{Biome, Biomes};
+use crate::generation::caves::CaveCarver;
+use crate::generation::decorations::{boulder, replaces, tree, Placement};
+use crate::generation::ores::{OreConfig, OrePass};
+use crate::generation::random::ChunkRng;
+use crate::generation::{GeneratorBlocks, MissingBlock, WorldGenerator};
+use crate::voxels::block::Blocks;
+use crate::voxels::chunk::{Chunk, CHUNK_D, CHUNK_H, CHUNK_W};
+use crate::voxels::voxel::Voxel;
+
+
+/// Средняя высота поверхности
+const BASE_HEIGHT: f64 = 4.0;
+/// Насколько поверхность отклоняется от средней высоты
+const HEIGHT_AMPLITUDE: f64 = 20.0;
+const HEIGHT_SCALE: f64 = 0.008;
+const OCTAVES: usize = 4;
+/// Климат меняется медленнее рельефа, чтобы биомы были крупными
+const CLIMATE_SCALE: f64 = 0.002;
+/// Сколько мест под украшения пробуется в столбе чанка
+const DECORATION_ATTEMPTS: usize = 6;
+
+
+/// Рельеф по карте высот из нескольких октав 2D шума,
+/// слои поверхности выбираются биомом по температуре и влажности.
+/// Поверх рельефа идут руды и пещеры
+pub struct TerrainGenerator {
+    height: OpenSimplex,
+    temperature: OpenSimplex,
+    humidity: OpenSimplex,
+    seed: u32,
+    ids: GeneratorBlocks,
+    biomes: Biomes,
+    ores: OrePass,
+    caves: CaveCarver,
+}
+
+
+impl TerrainGenerator {
+    /// id блоков рельефа и руд берутся по именам из `blocks`
+    pub fn new(seed: u32, blocks: &Blocks, ores: &[OreConfig]) -> Result<Self, MissingBlock> {
+        let ids = GeneratorBlocks::resolve(blocks)?;
+        Ok(Self {
+            height: OpenSimplex::new(seed),
+            temperature: OpenSimplex::new(seed.wrapping_add(1)),
+            humidity: OpenSimplex::new(seed.wrapping_add(2)),
+            seed,
+            ids,
+            biomes: Biomes::new(&ids),
+            ores: OrePass::new(seed, ids.stone, ores, blocks)?,
+            caves: CaveCarver::new(seed),
+        })
+    }
+
+    /// Высота верхнего блока столба. Зависит только от мировых x и z,
+    /// поэтому соседние чанки сходятся на границе
+    pub fn height_at(&self, x: isize, z: isize) -> isize {
+        let mut value = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = HEIGHT_SCALE;
+        let mut total = 0.0;
+        for _ in 0..OCTAVES {
+            value += self.height.get([x as f64 * frequency, z as f64 * frequency]) * amplitude;
+            total += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        (BASE_HEIGHT + value / total * HEIGHT_AMPLITUDE).floor() as isize
+    }
+
+    pub fn biome(&self, x: isize, z: isize) -> Biome {
+        let point = [x as f64 * CLIMATE_SCALE, z as f64 * CLIMATE_SCALE];
+        self.biomes.select(self.temperature.get(point), self.humidity.get(point))
+    }
+}
+
+
+impl WorldGenerator for TerrainGenerator {
+    fn generate(&self, cx: isize, cy: isize, cz: isize, voxels: &mut [Voxel]) {
+        let mut heights = vec![0; (CHUNK_W * CHUNK_D) as usize];
+        for z in 0..CHUNK_D {
+            for x in 0..CHUNK_W {
+                let real_x = x + cx * CHUNK_W;
+                let real_z = z + cz * CHUNK_D;
+                let height = self.height_at(real_x, real_z);
+                heights[(z * CHUNK_W + x) as usize] = height;
+                if height < cy * CHUNK_H {
+                    continue;
+                }
+                let biome = self.biome(real_x, real_z);
+                for y in 0..CHUNK_H {
+                    let real_y = y + cy * CHUNK_H;
+                    if real_y > height {
+                        break;
+                    }
+                    voxels[Chunk::index(x as usize, y as usize, z as usize)].id = biome.block_at(height - real_y);
+                }
+            }
+        }
+
+        self.ores.apply(cx, cy, cz, voxels);
+        self.caves.carve(cx, cy, cz, &heights, voxels);
+    }
+
+    fn surface_height(&self, x: isize, z: isize) -> Option<isize> {
+        Some(self.height_at(x, z))
+    }
+
+    fn decorate(&self, cx: isize, cy: isize, cz: isize) -> Vec<Placement> {
+        // попытки зависят только от столба чанков, а украшение достаётся тому чанку, где стоит его основание
+        let mut rng = ChunkRng::new(self.seed, cx, 0, cz, 100);
+        let mut placements = Vec::new();
+        for _ in 0..DECORATION_ATTEMPTS {
+            let x = cx * CHUNK_W + rng.below(CHUNK_W as usize) as isize;
+            let z = cz * CHUNK_D + rng.below(CHUNK_D as usize) as isize;
+            let chance = rng.next_f64();
+
+            let height = self.height_at(x, z);
+            if (height + 1).div_euclid(CHUNK_H) != cy || self.caves.is_cave(x, height, z, height) {
+                continue;
+            }
+            let biome = self.biome(x, z);
+            let mut feature_rng = ChunkRng::new(self.seed, x, height, z, 101);
+            if chance < biome.trees {
+                tree(x, height + 1, z, &self.ids, &mut feature_rng, &mut placements);
+            } else if chance < biome.trees + biome.boulders {
+                boulder(x, height + 1, z, &self.ids, &mut feature_rng, &mut placements);
+            }
+        }
+        placements
+    }
+
+    fn replaces(&self, current: Voxel, new: Voxel) -> bool {
+        replaces(current, new, &self.ids)
+    }
+}
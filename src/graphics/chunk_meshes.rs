@@ -0,0 +1,339 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::graphics::mesh::{Mesh, MeshBuilder, MeshError};
+use crate::graphics::occlusion::Connectivity;
+use crate::graphics::voxel_renderer::{ChunkGeometry, ChunkVertices, CHUNK_LAYOUT};
+use crate::math::Vec3;
+use crate::voxels::chunk::{CHUNK_D, CHUNK_H, CHUNK_W};
+
+
+/// Сколько мешей выгруженных чанков держать про запас
+const MAX_FREE_MESHES: usize = 256;
+
+/// На каком расстоянии в чанках от камеры прозрачные грани пересортировываются при движении камеры.
+/// Дальние чанки сортируются один раз при загрузке, порядок граней в них почти не меняется
+const SORT_DISTANCE: isize = 2;
+
+
+/// Счётчики мешей чанков и памяти под них на GPU
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MeshStats {
+    pub meshes: usize,
+    /// Из них меши прозрачных граней
+    pub translucent: usize,
+    /// Меши выгруженных чанков, ждущие новый чанк
+    pub free_meshes: usize,
+    /// Сколько раз пришлось создавать новый VAO с буферами
+    pub created: usize,
+    pub triangles: usize,
+    /// Байт занято вершинами и индексами
+    pub used_bytes: usize,
+    /// Байт выделено под буферы, включая запас и меши в резерве
+    pub allocated_bytes: usize,
+}
+
+
+impl MeshStats {
+    pub fn free_bytes(&self) -> usize {
+        debug_assert!(self.used_bytes <= self.allocated_bytes, "mesh uses more than it allocated: {:?}", self);
+        self.allocated_bytes.saturating_sub(self.used_bytes)
+    }
+}
+
+
+/// Память меша на GPU. Пул считает её через трейт, поэтому проверяется в тестах без GL контекста
+trait GpuMemory {
+    fn element_count(&self) -> usize;
+    fn gpu_bytes(&self) -> usize;
+    fn gpu_capacity(&self) -> usize;
+}
+
+
+impl GpuMemory for Mesh {
+    fn element_count(&self) -> usize {
+        Mesh::element_count(self)
+    }
+
+    fn gpu_bytes(&self) -> usize {
+        Mesh::gpu_bytes(self)
+    }
+
+    fn gpu_capacity(&self) -> usize {
+        Mesh::gpu_capacity(self)
+    }
+}
+
+
+/// Меши выгруженных чанков в резерве и счётчик созданных мешей
+struct MeshPool<M> {
+    free: Vec<M>,
+    created: usize,
+}
+
+
+impl<M> Default for MeshPool<M> {
+    fn default() -> Self {
+        Self { free: Vec::new(), created: 0 }
+    }
+}
+
+
+impl<M: GpuMemory> MeshPool<M> {
+    /// Меш из резерва, перезалитый `reload`, или новый из `create`.
+    /// Если перезалить не вышло, меш остаётся в резерве
+    fn take<E>(
+        &mut self,
+        reload: impl FnOnce(&mut M) -> Result<(), E>,
+        create: impl FnOnce() -> Result<M, E>,
+    ) -> Result<M, E> {
+        match self.free.pop() {
+            Some(mut mesh) => match reload(&mut mesh) {
+                Ok(()) => Ok(mesh),
+                Err(err) => {
+                    self.free.push(mesh);
+                    Err(err)
+                }
+            },
+            None => {
+                let mesh = create()?;
+                self.created += 1;
+                Ok(mesh)
+            }
+        }
+    }
+
+    /// Кладёт меш в резерв, а сверх `MAX_FREE_MESHES` освобождает его
+    fn release(&mut self, mesh: M) {
+        if self.free.len() < MAX_FREE_MESHES {
+            self.free.push(mesh);
+        }
+    }
+
+    /// Счётчики по мешам в работе `live` и мешам в резерве
+    fn stats<'a>(&self, live: impl Iterator<Item = &'a M>) -> MeshStats where M: 'a {
+        let mut stats = MeshStats { free_meshes: self.free.len(), created: self.created, ..MeshStats::default() };
+        for mesh in live {
+            stats.meshes += 1;
+            stats.triangles += mesh.element_count() / 3;
+            stats.used_bytes += mesh.gpu_bytes();
+            stats.allocated_bytes += mesh.gpu_capacity();
+        }
+        for mesh in &self.free {
+            stats.allocated_bytes += mesh.gpu_capacity();
+        }
+        stats
+    }
+}
+
+
+/// Прозрачные грани чанка. Вершины остаются на CPU, чтобы пересортировывать грани вслед за камерой
+struct Translucent {
+    mesh: Mesh,
+    vertices: ChunkVertices,
+    /// Воксель камеры, для которого грани отсортированы в меше
+    sorted_for: Option<(isize, isize, isize)>,
+}
+
+
+fn chunk_corner(key: &(isize, isize, isize)) -> Vec3 {
+    Vec3::new((key.0 * CHUNK_W) as f32, (key.1 * CHUNK_H) as f32, (key.2 * CHUNK_D) as f32)
+}
+
+
+fn eye_voxel(eye: Vec3) -> (isize, isize, isize) {
+    let voxel = eye.floor();
+    (voxel.x as isize, voxel.y as isize, voxel.z as isize)
+}
+
+
+/// Меши чанков. Перестроенный чанк перезаливается в свой же меш, а меш выгруженного чанка
+/// уходит в резерв и достаётся следующему, так что VAO и буферы создаются, только когда резерв пуст
+#[derive(Default)]
+pub struct ChunkMeshes {
+    meshes: HashMap<(isize, isize, isize), Mesh>,
+    /// Только у чанков, где есть прозрачные грани
+    translucent: HashMap<(isize, isize, isize), Translucent>,
+    /// Связность граней чанков из последней сборки меша
+    connectivity: HashMap<(isize, isize, isize), Connectivity>,
+    pool: MeshPool<Mesh>,
+}
+
+
+impl ChunkMeshes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Меш из резерва или новый, с загруженными `vertices`
+    fn take(&mut self, vertices: &ChunkVertices) -> Result<Mesh, MeshError> {
+        self.pool.take(
+            |mesh| vertices.reload(mesh),
+            || MeshBuilder::new(CHUNK_LAYOUT).vertices(&vertices.vertices).indices(&vertices.indices).build(),
+        )
+    }
+
+    fn release(&mut self, mesh: Mesh) {
+        self.pool.release(mesh);
+    }
+
+    /// Загружает меши чанка, прозрачные грани сортируются для камеры в `eye`.
+    /// Только в потоке с GL контекстом
+    pub fn upload(&mut self, key: (isize, isize, isize), geometry: &ChunkGeometry, eye: Vec3) -> Result<(), MeshError> {
+        self.connectivity.insert(key, geometry.connectivity);
+        match self.meshes.get_mut(&key) {
+            Some(mesh) => geometry.opaque.reload(mesh)?,
+            None => {
+                let mesh = self.take(&geometry.opaque)?;
+                self.meshes.insert(key, mesh);
+            }
+        }
+
+        if geometry.translucent.indices.is_empty() {
+            if let Some(translucent) = self.translucent.remove(&key) {
+                self.release(translucent.mesh);
+            }
+            return Ok(());
+        }
+        let mut vertices = geometry.translucent.clone();
+        vertices.sort_back_to_front(eye - chunk_corner(&key));
+        let sorted_for = Some(eye_voxel(eye));
+        match self.translucent.get_mut(&key) {
+            Some(translucent) => {
+                vertices.reload(&mut translucent.mesh)?;
+                translucent.vertices = vertices;
+                translucent.sorted_for = sorted_for;
+            }
+            None => {
+                let mesh = self.take(&vertices)?;
+                self.translucent.insert(key, Translucent { mesh, vertices, sorted_for });
+            }
+        }
+        Ok(())
+    }
+
+    /// Освобождает меши чанков, для которых `keep` вернул false
+    pub fn retain(&mut self, mut keep: impl FnMut(&(isize, isize, isize)) -> bool) {
+        let removed: Vec<_> = self.meshes.keys().filter(|key| !keep(key)).copied().collect();
+        for key in removed {
+            self.connectivity.remove(&key);
+            if let Some(mesh) = self.meshes.remove(&key) {
+                self.release(mesh);
+            }
+            if let Some(translucent) = self.translucent.remove(&key) {
+                self.release(translucent.mesh);
+            }
+        }
+    }
+
+    /// Непрозрачные меши чанков
+    pub fn iter(&self) -> impl Iterator<Item = (&(isize, isize, isize), &Mesh)> {
+        self.meshes.iter()
+    }
+
+    /// Пересортировывает прозрачные грани от дальних к ближним, если камера перешла в другой воксель.
+    /// Пересортировываются только чанки из `shown` не дальше `SORT_DISTANCE` от камеры
+    pub fn sort_translucent(&mut self, eye: Vec3, shown: &HashSet<(isize, isize, isize)>) -> Result<(), MeshError> {
+        let voxel = eye_voxel(eye);
+        let center = (voxel.0.div_euclid(CHUNK_W), voxel.1.div_euclid(CHUNK_H), voxel.2.div_euclid(CHUNK_D));
+        for (key, translucent) in &mut self.translucent {
+            let distance = (key.0 - center.0).abs().max((key.1 - center.1).abs()).max((key.2 - center.2).abs());
+            if translucent.sorted_for == Some(voxel) || distance > SORT_DISTANCE || !shown.contains(key) {
+                continue;
+            }
+            translucent.vertices.sort_back_to_front(eye - chunk_corner(key));
+            translucent.vertices.reload(&mut translucent.mesh)?;
+            translucent.sorted_for = Some(voxel);
+        }
+        Ok(())
+    }
+
+    /// Прозрачные меши чанков от дальних к ближним
+    pub fn translucent_back_to_front(&self, eye: Vec3) -> Vec<(&(isize, isize, isize), &Mesh)> {
+        let half = Vec3::new(CHUNK_W as f32, CHUNK_H as f32, CHUNK_D as f32) / 2.0;
+        let mut meshes: Vec<_> = self.translucent.iter()
+            .map(|(key, translucent)| ((chunk_corner(key) + half).distance_squared(eye), key, &translucent.mesh))
+            .collect();
+        meshes.sort_by(|a, b| b.0.total_cmp(&a.0));
+        meshes.into_iter().map(|(_, key, mesh)| (key, mesh)).collect()
+    }
+
+    /// Связность граней чанка, None - чанк ещё не собирался
+    pub fn connectivity(&self, key: &(isize, isize, isize)) -> Option<Connectivity> {
+        self.connectivity.get(key).copied()
+    }
+
+    pub fn stats(&self) -> MeshStats {
+        let translucent = self.translucent.values().map(|translucent| &translucent.mesh);
+        MeshStats { translucent: self.translucent.len(), ..self.pool.stats(self.meshes.values().chain(translucent)) }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Меш без GL: занято `used` байт из `capacity`
+    struct FakeMesh {
+        used: usize,
+        capacity: usize,
+    }
+
+    impl GpuMemory for FakeMesh {
+        fn element_count(&self) -> usize {
+            self.used / 4
+        }
+
+        fn gpu_bytes(&self) -> usize {
+            self.used
+        }
+
+        fn gpu_capacity(&self) -> usize {
+            self.capacity
+        }
+    }
+
+    fn take(pool: &mut MeshPool<FakeMesh>, used: usize) -> FakeMesh {
+        pool.take::<()>(
+            |mesh| {
+                mesh.used = used;
+                Ok(())
+            },
+            || Ok(FakeMesh { used, capacity: used.next_power_of_two() }),
+        ).unwrap()
+    }
+
+    #[test]
+    fn released_meshes_are_reused_up_to_the_cap() {
+        let mut pool = MeshPool::default();
+        let meshes: Vec<_> = (0..MAX_FREE_MESHES + 3).map(|_| take(&mut pool, 12)).collect();
+        assert_eq!(pool.created, MAX_FREE_MESHES + 3);
+        for mesh in meshes {
+            pool.release(mesh);
+        }
+        assert_eq!(pool.free.len(), MAX_FREE_MESHES);
+
+        let mesh = take(&mut pool, 6);
+        assert_eq!((mesh.used, mesh.capacity), (6, 16));
+        assert_eq!(pool.created, MAX_FREE_MESHES + 3);
+
+        // меш, который не удалось перезалить, не теряется
+        assert!(pool.take(|_| Err(()), || Ok(FakeMesh { used: 0, capacity: 0 })).is_err());
+        assert_eq!(pool.free.len(), MAX_FREE_MESHES - 1);
+    }
+
+    #[test]
+    fn stats_count_live_and_free_memory() {
+        let mut pool = MeshPool::default();
+        let live = [take(&mut pool, 12), take(&mut pool, 30)];
+        let spare = take(&mut pool, 100);
+        pool.release(spare);
+
+        let stats = pool.stats(live.iter());
+        assert_eq!((stats.meshes, stats.free_meshes, stats.created), (2, 1, 3));
+        assert_eq!(stats.triangles, 12 / 4 / 3 + 30 / 4 / 3);
+        assert_eq!(stats.used_bytes, 42);
+        assert_eq!(stats.allocated_bytes, 16 + 32 + 128);
+        assert_eq!(stats.free_bytes(), 16 + 32 + 128 - 42);
+    }
+}
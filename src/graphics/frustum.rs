@@ -0,0 +1,73 @@
+use glam::{Mat4, Vec3, Vec4};
+
+
+/// Пирамида видимости камеры: шесть плоскостей, нормали смотрят внутрь.
+/// Только математика, GL не нужен
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+
+impl Frustum {
+    /// Плоскости из матрицы `projection * view` (метод Gribb-Hartmann)
+    pub fn from_matrix(matrix: Mat4) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| matrix.row(i));
+        let planes = [w + x, w - x, w + y, w - y, w + z, w - z].map(|plane| plane / plane.truncate().length());
+        Self { planes }
+    }
+
+    /// Пересекает ли пирамиду box с углами `min` и `max`. Для box, лежащего у ребра пирамиды
+    /// снаружи, иногда отвечает true: лучше нарисовать лишнее, чем потерять видимое
+    pub fn intersects_box(&self, min: Vec3, max: Vec3) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            // угол box, дальше всех продвинутый вдоль нормали
+            let corner = Vec3::select(normal.cmpge(Vec3::ZERO), max, min);
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::Camera;
+
+    fn frustum(camera: &Camera) -> Frustum {
+        Frustum::from_matrix(camera.get_projection(1280.0, 720.0) * camera.get_view())
+    }
+
+    fn unit_box(frustum: &Frustum, x: f32, y: f32, z: f32) -> bool {
+        frustum.intersects_box(Vec3::new(x, y, z), Vec3::new(x + 1.0, y + 1.0, z + 1.0))
+    }
+
+    #[test]
+    fn boxes_outside_the_view_are_culled() {
+        // камера смотрит в -Z
+        let frustum = frustum(&Camera::init(Vec3::ZERO, 70.0_f32.to_radians()));
+
+        assert!(unit_box(&frustum, -0.5, -0.5, -10.0));
+        assert!(!unit_box(&frustum, -0.5, -0.5, 10.0), "behind the camera");
+        assert!(!unit_box(&frustum, -0.5, -0.5, -500.0), "beyond the far plane");
+        assert!(!unit_box(&frustum, 40.0, -0.5, -10.0), "far to the right");
+        assert!(!unit_box(&frustum, -0.5, 20.0, -10.0), "far above");
+        // box, в котором стоит камера, виден всегда
+        assert!(unit_box(&frustum, -0.5, -0.5, -0.5));
+        // большой box, пересекающий край пирамиды
+        assert!(frustum.intersects_box(Vec3::new(5.0, -1.0, -12.0), Vec3::new(40.0, 1.0, -8.0)));
+    }
+
+    #[test]
+    fn far_plane_follows_view_distance() {
+        let mut settings = crate::settings::RenderSettings { view_distance: 16.0, ..Default::default() };
+        let mut camera = Camera::init(Vec3::ZERO, settings.fov.to_radians());
+        camera.far = settings.far_plane();
+        // дальний край последнего загруженного чанка впереди ещё не срезан
+        assert!(unit_box(&frustum(&camera), -0.5, -0.5, -256.0));
+
+        settings.change_view_distance(-12.0);
+        camera.far = settings.far_plane();
+        assert!(!unit_box(&frustum(&camera), -0.5, -0.5, -256.0));
+    }
+}
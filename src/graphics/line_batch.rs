@@ -1,7 +1,9 @@
-use crate::graphics::mesh::Mesh;
+use crate::graphics::mesh::{Attribute, Mesh, MeshBuilder, VertexLayout};
 use crate::settings::LINES;
 
 const LB_VERTEX_SIZE: usize = 7;
+/// Вершина линии: позиция и цвет rgba
+const LB_LAYOUT: VertexLayout = VertexLayout::new(&[Attribute::float(3), Attribute::float(4)]);
 
 pub struct LineBatch {
     capacity: usize,
@@ -13,9 +15,8 @@ pub struct LineBatch {
 
 impl LineBatch {
     pub fn init(capacity: usize) -> Self {
-        let attrs = [3, 4, 0];
         let buffer = Vec::with_capacity(capacity * LB_VERTEX_SIZE * 2);
-        let mesh = Mesh::new(buffer.as_ptr(), 0, attrs.as_ptr());
+        let mesh = MeshBuilder::new(LB_LAYOUT).build().expect("an empty mesh is always valid");
         let index = 0;
 
         Self { capacity, index, buffer, mesh, }
@@ -72,7 +73,8 @@ impl LineBatch {
             return;
         }
 
-        self.mesh.reload(self.buffer.as_ptr(), self.buffer.len() / LB_VERTEX_SIZE);
+        // линии добавляются только целыми вершинами
+        self.mesh.reload(&self.buffer, &[]).expect("line batch holds whole vertices");
         self.mesh.draw(LINES);
         self.buffer.clear();
     }
@@ -1,20 +1,217 @@
+use std::fmt;
+
 use gl::types::*;
 
+
+/// Тип компонент атрибута вершины в буфере
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttrType {
+    F32,
+    U8,
+    U16,
+}
+
+
+/// Как шейдер видит значения атрибута
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttrMode {
+    /// Как float: целые переводятся в дробные без изменения величины
+    Float,
+    /// Целые без знака приводятся к 0..1
+    Normalized,
+    /// Целые остаются целыми: `uint`/`uvec` в шейдере
+    Integer,
+}
+
+
+/// Атрибут вершины: `size` компонент типа `ty`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Attribute {
+    pub size: i32,
+    pub ty: AttrType,
+    pub mode: AttrMode,
+}
+
+
+impl Attribute {
+    pub const fn float(size: i32) -> Self {
+        Self { size, ty: AttrType::F32, mode: AttrMode::Float }
+    }
+
+    pub const fn new(size: i32, ty: AttrType, mode: AttrMode) -> Self {
+        Self { size, ty, mode }
+    }
+
+    pub const fn bytes(&self) -> usize {
+        let component = match self.ty {
+            AttrType::F32 => size_of::<f32>(),
+            AttrType::U8 => size_of::<u8>(),
+            AttrType::U16 => size_of::<u16>(),
+        };
+        self.size as usize * component
+    }
+
+    fn gl_type(&self) -> GLenum {
+        match self.ty {
+            AttrType::F32 => gl::FLOAT,
+            AttrType::U8 => gl::UNSIGNED_BYTE,
+            AttrType::U16 => gl::UNSIGNED_SHORT,
+        }
+    }
+}
+
+
+/// Атрибуты вершины по порядку `location` в шейдере, без промежутков между ними.
+/// После атрибутов может идти `padding` неиспользуемых байт, чтобы выровнять следующую вершину
+#[derive(Clone, Copy, Debug)]
+pub struct VertexLayout {
+    pub attributes: &'static [Attribute],
+    pub padding: usize,
+}
+
+
+impl VertexLayout {
+    pub const fn new(attributes: &'static [Attribute]) -> Self {
+        Self { attributes, padding: 0 }
+    }
+
+    pub const fn with_padding(self, padding: usize) -> Self {
+        Self { padding, ..self }
+    }
+
+    /// Размер вершины в байтах
+    pub const fn vertex_size(&self) -> usize {
+        let mut size = self.padding;
+        let mut i = 0;
+        while i < self.attributes.len() {
+            size += self.attributes[i].bytes();
+            i += 1;
+        }
+        size
+    }
+}
+
+
+/// Числа, из которых можно собрать буфер вершин: без padding, любой набор байт допустим
+pub trait VertexData: Copy {}
+
+impl VertexData for f32 {}
+impl VertexData for u8 {}
+impl VertexData for u16 {}
+impl VertexData for u32 {}
+
+
+fn as_bytes<T: VertexData>(data: &[T]) -> &[u8] {
+    // SAFETY: VertexData реализован только для чисел без padding, а у u8 выравнивание 1
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, size_of_val(data)) }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MeshError {
+    /// Длина буфера в байтах не делится на размер вершины
+    PartialVertex { bytes: usize, vertex_size: usize },
+    /// Индекс ссылается на вершину за концом буфера
+    IndexOutOfRange { index: u32, vertices: usize },
+}
+
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::PartialVertex { bytes, vertex_size } => {
+                write!(f, "{} bytes of vertices is not a multiple of the {} byte vertex", bytes, vertex_size)
+            }
+            MeshError::IndexOutOfRange { index, vertices } => {
+                write!(f, "index {} is out of range for {} vertices", index, vertices)
+            }
+        }
+    }
+}
+
+
+impl std::error::Error for MeshError {}
+
+
+/// Записывает `data` в начало буфера, привязанного к `target`. Буфер перевыделяется с запасом,
+/// только если данные не помещаются в `capacity`
+fn write_buffer(target: GLenum, capacity: &mut usize, data: &[u8]) {
+    unsafe {
+        if data.len() > *capacity {
+            *capacity = data.len().next_power_of_two();
+            gl::BufferData(target, *capacity as GLsizeiptr, std::ptr::null(), gl::DYNAMIC_DRAW);
+        }
+        if !data.is_empty() {
+            gl::BufferSubData(target, 0, data.len() as GLsizeiptr, data.as_ptr() as *const std::ffi::c_void);
+        }
+    }
+}
+
+
+/// Проверяет буфер и индексы по раскладке и возвращает число вершин
+fn validate(layout: &VertexLayout, vertices: &[u8], indices: &[u32]) -> Result<usize, MeshError> {
+    let vertex_size = layout.vertex_size();
+    if !vertices.len().is_multiple_of(vertex_size) {
+        return Err(MeshError::PartialVertex { bytes: vertices.len(), vertex_size });
+    }
+    let count = vertices.len() / vertex_size;
+    if let Some(&index) = indices.iter().find(|&&index| index as usize >= count) {
+        return Err(MeshError::IndexOutOfRange { index, vertices: count });
+    }
+    Ok(count)
+}
+
+
+/// Собирает меш из вершин по раскладке и, если заданы, индексов
+pub struct MeshBuilder<'a> {
+    layout: VertexLayout,
+    vertices: &'a [u8],
+    indices: &'a [u32],
+}
+
+
+impl<'a> MeshBuilder<'a> {
+    pub fn new(layout: VertexLayout) -> Self {
+        Self { layout, vertices: &[], indices: &[] }
+    }
+
+    pub fn vertices<T: VertexData>(mut self, vertices: &'a [T]) -> Self {
+        self.vertices = as_bytes(vertices);
+        self
+    }
+
+    /// С индексами меш рисуется через `DrawElements`
+    pub fn indices(mut self, indices: &'a [u32]) -> Self {
+        self.indices = indices;
+        self
+    }
+
+    /// Создаёт VAO и буферы. Только в потоке с GL контекстом
+    pub fn build(self) -> Result<Mesh, MeshError> {
+        validate(&self.layout, self.vertices, self.indices)?;
+        let mut mesh = Mesh::create(self.layout);
+        mesh.reload(self.vertices, self.indices)?;
+        Ok(mesh)
+    }
+}
+
+
 pub struct Mesh {
     vao: GLuint,
     vbo: GLuint,
+    /// Индексный буфер, создаётся при первой загрузке индексов
+    ebo: GLuint,
     vertices: usize,
-    _vertex_size: usize
+    indices: usize,
+    /// Выделенный размер буферов в байтах, не меньше загруженных данных
+    vertex_capacity: usize,
+    index_capacity: usize,
+    layout: VertexLayout,
 }
 
 impl Mesh {
-    pub fn new(buffer: *const f32, vertices: usize, attrs: *const i32) -> Self {
-        let mut _vertex_size = 0;
-        let mut i = 0;
-        while unsafe { *attrs.offset(i) } != 0 {
-            _vertex_size += unsafe { *attrs.offset(i) as usize };
-            i += 1;
-        }
+    fn create(layout: VertexLayout) -> Self {
+        let vertex_size = layout.vertex_size();
 
         let mut vao = 0;
         let mut vbo = 0;
@@ -25,23 +222,29 @@ impl Mesh {
 
             gl::BindVertexArray(vao);
             gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-            gl::BufferData(gl::ARRAY_BUFFER, (size_of::<f32>() * _vertex_size * vertices) as GLsizeiptr, buffer as *const std::ffi::c_void, gl::STATIC_DRAW);
 
             let mut offset = 0;
-            let mut i = 0;
-            while *attrs.offset(i) != 0 {
-                let size = *attrs.offset(i) as GLint;
-                gl::VertexAttribPointer(
-                    i as GLuint,
-                    size,
-                    gl::FLOAT,
-                    gl::FALSE,
-                    (_vertex_size * size_of::<f32>()) as GLint,
-                    (offset * size_of::<f32>()) as *const std::ffi::c_void,
-                );
+            for (i, attr) in layout.attributes.iter().enumerate() {
+                let pointer = offset as *const std::ffi::c_void;
+                match attr.mode {
+                    AttrMode::Integer => gl::VertexAttribIPointer(
+                        i as GLuint,
+                        attr.size,
+                        attr.gl_type(),
+                        vertex_size as GLint,
+                        pointer,
+                    ),
+                    AttrMode::Float | AttrMode::Normalized => gl::VertexAttribPointer(
+                        i as GLuint,
+                        attr.size,
+                        attr.gl_type(),
+                        if attr.mode == AttrMode::Normalized { gl::TRUE } else { gl::FALSE },
+                        vertex_size as GLint,
+                        pointer,
+                    ),
+                }
                 gl::EnableVertexAttribArray(i as GLuint);
-                offset += size as usize;
-                i += 1;
+                offset += attr.bytes();
             }
 
             gl::BindVertexArray(0);
@@ -50,30 +253,65 @@ impl Mesh {
         Mesh {
             vao,
             vbo,
-            vertices,
-            _vertex_size
+            ebo: 0,
+            vertices: 0,
+            indices: 0,
+            vertex_capacity: 0,
+            index_capacity: 0,
+            layout,
         }
     }
 
 
-    pub fn reload(&mut self, buffer: *const f32, vertices: usize) {
+    /// Перезаливает вершины и индексы в те же VAO и буферы.
+    /// Меш, однажды получивший индексы, дальше рисуется только по ним
+    pub fn reload<T: VertexData>(&mut self, vertices: &[T], indices: &[u32]) -> Result<(), MeshError> {
+        let vertices = as_bytes(vertices);
+        let count = validate(&self.layout, vertices, indices)?;
         unsafe {
             gl::BindVertexArray(self.vao);
             gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-            gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (size_of::<f32>() * self._vertex_size * vertices) as GLsizeiptr,
-                buffer as *const std::ffi::c_void,
-                gl::STATIC_DRAW,
-            );
+            write_buffer(gl::ARRAY_BUFFER, &mut self.vertex_capacity, vertices);
+
+            if !indices.is_empty() && self.ebo == 0 {
+                gl::GenBuffers(1, &mut self.ebo);
+            }
+            if self.ebo != 0 {
+                // индексный буфер запоминается в VAO, поэтому привязывается при привязанном VAO
+                gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebo);
+                write_buffer(gl::ELEMENT_ARRAY_BUFFER, &mut self.index_capacity, as_bytes(indices));
+            }
+
+            gl::BindVertexArray(0);
         }
-        self.vertices = vertices;
+        self.vertices = count;
+        self.indices = indices.len();
+        Ok(())
+    }
+
+    /// Сколько вершин уходит в отрисовку: для индексированного меша - число индексов
+    pub fn element_count(&self) -> usize {
+        if self.ebo != 0 { self.indices } else { self.vertices }
+    }
+
+    /// Сколько байт занимают загруженные вершины и индексы
+    pub fn gpu_bytes(&self) -> usize {
+        self.layout.vertex_size() * self.vertices + size_of::<u32>() * self.indices
+    }
+
+    /// Сколько байт выделено под буферы меша на GPU
+    pub fn gpu_capacity(&self) -> usize {
+        self.vertex_capacity + self.index_capacity
     }
 
     pub fn draw(&self, primitive: GLenum){
         unsafe {
             gl::BindVertexArray(self.vao);
-            gl::DrawArrays(primitive, 0, self.vertices as GLsizei);
+            if self.ebo != 0 {
+                gl::DrawElements(primitive, self.indices as GLsizei, gl::UNSIGNED_INT, std::ptr::null());
+            } else {
+                gl::DrawArrays(primitive, 0, self.vertices as GLsizei);
+            }
             gl::BindVertexArray(0);
         }
     }
@@ -85,6 +323,36 @@ impl Drop for Mesh {
         unsafe {
             gl::DeleteVertexArrays(1, &self.vao);
             gl::DeleteBuffers(1, &self.vbo);
+            if self.ebo != 0 {
+                gl::DeleteBuffers(1, &self.ebo);
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT: VertexLayout = VertexLayout::new(&[
+        Attribute::float(3),
+        Attribute::new(2, AttrType::U16, AttrMode::Normalized),
+    ]);
+
+    #[test]
+    fn layout_validates_buffers() {
+        assert_eq!(LAYOUT.vertex_size(), 16);
+        assert_eq!(LAYOUT.with_padding(4).vertex_size(), 20);
+        let vertices = [0.0f32; 8];
+        assert_eq!(validate(&LAYOUT, as_bytes(&vertices), &[0, 1, 1]), Ok(2));
+        assert_eq!(
+            validate(&LAYOUT, as_bytes(&vertices[..7]), &[]),
+            Err(MeshError::PartialVertex { bytes: 28, vertex_size: 16 })
+        );
+        assert_eq!(
+            validate(&LAYOUT, as_bytes(&vertices), &[0, 2]),
+            Err(MeshError::IndexOutOfRange { index: 2, vertices: 2 })
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+
+use crate::graphics::voxel_renderer::{ChunkGeometry, MeshingMode};
+use crate::graphics::VoxelRenderer;
+use crate::voxels::Blocks;
+use crate::voxels::neighbourhood::{NeighbourSnapshot, Neighbourhood};
+
+
+thread_local! {
+    /// У каждого рабочего потока свой построитель, чтобы не выделять маску слоя на каждый чанк
+    static RENDERER: RefCell<VoxelRenderer> = RefCell::new(VoxelRenderer::new());
+}
+
+
+/// Готовые меши чанка и номер задания, из которого они получены
+struct Built {
+    key: (isize, isize, isize),
+    ticket: u64,
+    geometry: ChunkGeometry,
+}
+
+
+/// Строит вершины чанков в пуле rayon по снимкам чанка и его соседей.
+/// У каждого чанка есть номер последнего задания: задания со старым номером
+/// не начинаются, а их результаты выбрасываются. Номера общие для всех чанков и только растут,
+/// так что задание выгруженного чанка не совпадёт по номеру с заданием после его загрузки
+pub struct Mesher {
+    blocks: Arc<Blocks>,
+    /// Режим для новых заданий. По умолчанию грань за гранью, слияние включается по F3
+    pub mode: MeshingMode,
+    tickets: HashMap<(isize, isize, isize), Arc<AtomicU64>>,
+    /// Номер последнего выданного задания
+    last_ticket: u64,
+    /// Уровень детализации последнего задания каждого чанка
+    lods: HashMap<(isize, isize, isize), u8>,
+    sender: Sender<Built>,
+    receiver: Receiver<Built>,
+}
+
+
+impl Mesher {
+    pub fn new(blocks: Arc<Blocks>) -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            blocks,
+            mode: MeshingMode::PerFace,
+            tickets: HashMap::new(),
+            last_ticket: 0,
+            lods: HashMap::new(),
+            sender,
+            receiver,
+        }
+    }
+
+    /// Ставит чанк в очередь на построение по снимку его соседства с детализацией `lod`
+    pub fn submit(&mut self, key: (isize, isize, isize), snapshot: NeighbourSnapshot, lod: u8) {
+        self.lods.insert(key, lod);
+        self.last_ticket += 1;
+        let ticket = self.last_ticket;
+        let current = Arc::clone(self.tickets.entry(key).or_default());
+        current.store(ticket, Ordering::SeqCst);
+        let blocks = Arc::clone(&self.blocks);
+        let mode = self.mode;
+        let sender = self.sender.clone();
+
+        rayon::spawn(move || {
+            // чанк успели изменить ещё раз, пока задание ждало очереди
+            if current.load(Ordering::SeqCst) != ticket {
+                return;
+            }
+            let chunks = Neighbourhood::from_snapshot(&snapshot);
+            let geometry = RENDERER.with(|renderer| renderer.borrow_mut().render(&chunks, &blocks, mode, lod));
+            let _ = sender.send(Built { key, ticket, geometry });
+        });
+    }
+
+    /// Забывает выгруженные чанки: их незаконченные задания больше не нужны
+    pub fn retain(&mut self, mut keep: impl FnMut(&(isize, isize, isize)) -> bool) {
+        self.tickets.retain(|key, current| {
+            let kept = keep(key);
+            if !kept {
+                // ждущие задания чанка не начнутся: номера заданий начинаются с 1
+                current.store(0, Ordering::SeqCst);
+            }
+            kept
+        });
+        self.lods.retain(|key, _| self.tickets.contains_key(key));
+    }
+
+    /// С какой детализацией чанк был поставлен в очередь последний раз
+    pub fn lod(&self, key: &(isize, isize, isize)) -> Option<u8> {
+        self.lods.get(key).copied()
+    }
+
+    /// Свежие результаты, готовые к загрузке на GPU
+    pub fn poll(&mut self) -> Vec<((isize, isize, isize), ChunkGeometry)> {
+        let mut ready = Vec::new();
+        while let Ok(built) = self.receiver.try_recv() {
+            let fresh = self.tickets.get(&built.key)
+                .is_some_and(|current| current.load(Ordering::SeqCst) == built.ticket);
+            if fresh {
+                ready.push((built.key, built.geometry));
+            }
+        }
+        ready
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::GeneratorKind;
+    use crate::graphics::voxel_renderer::CHUNK_LAYOUT;
+    use crate::test_util::{blocks, loaded_world, settings};
+    use crate::voxels::Chunks;
+
+    /// Ждёт все поставленные задания и возвращает то, что из них пропустит `poll`.
+    /// Каждое задание держит копию sender, поэтому старый канал закрывается, когда закончилось последнее
+    fn finish(mesher: &mut Mesher) -> Vec<((isize, isize, isize), ChunkGeometry)> {
+        let (sender, receiver) = channel();
+        drop(std::mem::replace(&mut mesher.sender, sender));
+        let finished = std::mem::replace(&mut mesher.receiver, receiver);
+        for built in finished.iter() {
+            mesher.sender.send(built).unwrap();
+        }
+        mesher.poll()
+    }
+
+    fn world_with_block() -> (Arc<Blocks>, Chunks) {
+        let blocks = Arc::new(blocks());
+        let mut chunks = loaded_world(&blocks, &settings(1, GeneratorKind::Empty), (0, 0, 0));
+        chunks.set(8, 8, 8, 1, &blocks).unwrap();
+        (blocks, chunks)
+    }
+
+    #[test]
+    fn jobs_from_before_unload_are_dropped() {
+        let (blocks, chunks) = world_with_block();
+        let mut mesher = Mesher::new(blocks);
+        mesher.submit((0, 0, 0), chunks.snapshot(0, 0, 0), 0);
+        mesher.retain(|_| false);
+        mesher.submit((0, 0, 0), chunks.snapshot(0, 0, 0), 0);
+        assert_eq!(finish(&mut mesher).len(), 1);
+    }
+
+    #[test]
+    fn resubmitted_chunk_keeps_only_the_last_job() {
+        let (blocks, chunks) = world_with_block();
+        let mut mesher = Mesher::new(blocks);
+        mesher.submit((0, 0, 0), chunks.snapshot(0, 0, 0), 0);
+        mesher.submit((0, 0, 0), chunks.snapshot(0, 0, 0), 1);
+
+        let ready = finish(&mut mesher);
+        assert_eq!(ready.len(), 1);
+        // блок в 8..9 попадает в меш только на полной детализации, клетки LOD 1 кратны 2
+        let vertices = &ready[0].1.opaque.vertices;
+        assert!(!vertices.is_empty());
+        assert!(vertices.chunks(CHUNK_LAYOUT.vertex_size()).all(|vertex| vertex[..3].iter().all(|coord| coord % 2 == 0)));
+    }
+}
@@ -3,8 +3,15 @@ mod texture;
 pub mod voxel_renderer;
 pub mod mesh;
 pub mod line_batch;
+pub mod mesher;
+pub mod chunk_meshes;
+pub mod frustum;
+pub mod occlusion;
 
 pub use shader::load_shader;
 pub use texture::Texture;
-pub use voxel_renderer::VoxelRenderer;
-pub use line_batch::LineBatch;
\ No newline at end of file
+pub use voxel_renderer::{MeshingMode, VoxelRenderer};
+pub use line_batch::LineBatch;
+pub use mesher::Mesher;
+pub use chunk_meshes::ChunkMeshes;
+pub use frustum::Frustum;
\ No newline at end of file
@@ -0,0 +1,178 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::voxels::chunk::{Chunk, CHUNK_D, CHUNK_H, CHUNK_VOL, CHUNK_W};
+use crate::voxels::Blocks;
+
+
+/// Соседи чанка в порядке граней: -x, +x, -y, +y, -z, +z
+const DIRECTIONS: [(isize, isize, isize); 6] = [(-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0), (0, 0, -1), (0, 0, 1)];
+
+
+fn opposite(face: usize) -> usize {
+    face ^ 1
+}
+
+
+/// Какие грани чанка связаны между собой через прозрачные воксели: бит `a * 6 + b`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Connectivity(u64);
+
+
+impl Connectivity {
+    /// Все грани связаны, как у пустого чанка
+    pub const ALL: Connectivity = Connectivity((1 << 36) - 1);
+
+    pub fn connects(&self, a: usize, b: usize) -> bool {
+        self.0 & (1 << (a * 6 + b)) != 0
+    }
+
+    /// Связывает между собой все грани из маски `faces`
+    fn join(&mut self, faces: u8) {
+        for a in 0..6 {
+            for b in 0..6 {
+                if faces & (1 << a) != 0 && faces & (1 << b) != 0 {
+                    self.0 |= 1 << (a * 6 + b);
+                }
+            }
+        }
+    }
+
+    /// Заливка по непрозрачным для света вокселям чанка: каждая связная область
+    /// пустоты связывает все грани, которых касается
+    pub fn of(chunk: &Chunk, blocks: &Blocks) -> Self {
+        let open = |x: isize, y: isize, z: isize| {
+            let voxel = chunk.get(x as usize, y as usize, z as usize);
+            voxel.id == 0 || blocks.get(voxel.id).is_some_and(|block| block.light_passing)
+        };
+
+        let mut connectivity = Connectivity::default();
+        let mut visited = vec![false; CHUNK_VOL];
+        let mut stack = Vec::new();
+        for start in 0..CHUNK_VOL {
+            // обратное к Chunk::index
+            let (x, y, z) = (start as isize % CHUNK_W, start as isize / (CHUNK_W * CHUNK_D), start as isize / CHUNK_W % CHUNK_D);
+            if visited[start] || !open(x, y, z) {
+                continue;
+            }
+
+            let mut faces = 0u8;
+            visited[start] = true;
+            stack.push((x, y, z));
+            while let Some((x, y, z)) = stack.pop() {
+                for (face, &(dx, dy, dz)) in DIRECTIONS.iter().enumerate() {
+                    let (nx, ny, nz) = (x + dx, y + dy, z + dz);
+                    if !(0..CHUNK_W).contains(&nx) || !(0..CHUNK_H).contains(&ny) || !(0..CHUNK_D).contains(&nz) {
+                        faces |= 1 << face;
+                        continue;
+                    }
+                    let index = Chunk::index(nx as usize, ny as usize, nz as usize);
+                    if !visited[index] && open(nx, ny, nz) {
+                        visited[index] = true;
+                        stack.push((nx, ny, nz));
+                    }
+                }
+            }
+            connectivity.join(faces);
+        }
+        connectivity
+    }
+}
+
+
+/// Чанки, которые можно увидеть из чанка `start`. Обход в ширину идёт от камеры и только
+/// удаляется от неё: в чанк нельзя шагнуть против уже пройденного направления, а выйти
+/// из чанка можно только через грань, связанную с той, через которую в него вошли.
+/// `connectivity` возвращает None для незагруженных чанков, за них обход не идёт
+pub fn visible_chunks(
+    start: (isize, isize, isize),
+    connectivity: impl Fn(&(isize, isize, isize)) -> Option<Connectivity>,
+) -> HashSet<(isize, isize, isize)> {
+    let mut visible = HashSet::from([start]);
+    let mut queue = VecDeque::from([(start, Connectivity::ALL, None::<usize>, 0u8)]);
+
+    while let Some((key, connections, entered, travelled)) = queue.pop_front() {
+        for (face, &(dx, dy, dz)) in DIRECTIONS.iter().enumerate() {
+            if travelled & (1 << opposite(face)) != 0 {
+                continue;
+            }
+            if entered.is_some_and(|entered| !connections.connects(entered, face)) {
+                continue;
+            }
+            let next = (key.0 + dx, key.1 + dy, key.2 + dz);
+            if visible.contains(&next) {
+                continue;
+            }
+            if let Some(next_connections) = connectivity(&next) {
+                visible.insert(next);
+                queue.push_back((next, next_connections, Some(opposite(face)), travelled | 1 << face));
+            }
+        }
+    }
+    visible
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::voxels::voxel::Voxel;
+    use crate::voxels::Block;
+
+    fn blocks() -> Blocks {
+        let mut blocks = Blocks::init();
+        let mut air = Block::new(0, "air", 0);
+        air.light_passing = true;
+        blocks.add(air);
+        blocks.add(Block::new(1, "stone", 2));
+        blocks
+    }
+
+    fn chunk(x: isize, y: isize, z: isize, solid: impl Fn(isize, isize, isize) -> bool) -> Chunk {
+        let mut voxels = vec![Voxel::default(); CHUNK_VOL];
+        for ly in 0..CHUNK_H {
+            for lz in 0..CHUNK_D {
+                for lx in 0..CHUNK_W {
+                    if solid(lx, ly, lz) {
+                        voxels[Chunk::index(lx as usize, ly as usize, lz as usize)].id = 1;
+                    }
+                }
+            }
+        }
+        Chunk::from_voxels(x, y, z, &voxels)
+    }
+
+    #[test]
+    fn wall_splits_chunk_faces() {
+        let blocks = blocks();
+        let wall = Connectivity::of(&chunk(0, 0, 0, |x, _, _| x == 8), &blocks);
+        assert!(!wall.connects(0, 1));
+        assert!(wall.connects(0, 2) && wall.connects(1, 2) && wall.connects(2, 3) && wall.connects(4, 5));
+
+        assert_eq!(Connectivity::of(&chunk(0, 0, 0, |_, _, _| false), &blocks), Connectivity::ALL);
+        assert_eq!(Connectivity::of(&chunk(0, 0, 0, |_, _, _| true), &blocks), Connectivity::default());
+    }
+
+    #[test]
+    fn terrain_hides_chunks_behind_it() {
+        let blocks = blocks();
+        // туннель вдоль X под слоем камня: из него видны сам туннель и соседи чанка камеры,
+        // но не поверхность над камнем
+        let mut world = HashMap::new();
+        let tunnel = |_: isize, y: isize, z: isize| !(6..10).contains(&y) || !(6..10).contains(&z);
+        for x in -1..=3 {
+            for z in -1..=1 {
+                world.insert((x, 0, z), chunk(x, 0, z, |lx, ly, lz| z != 0 || tunnel(lx, ly, lz)));
+                world.insert((x, 1, z), chunk(x, 1, z, |_, _, _| true));
+                world.insert((x, 2, z), chunk(x, 2, z, |_, _, _| false));
+            }
+        }
+
+        let connectivity: HashMap<_, _> = world.iter().map(|(key, chunk)| (*key, Connectivity::of(chunk, &blocks))).collect();
+        let visible = visible_chunks((0, 0, 0), |key| connectivity.get(key).copied());
+
+        let expected = HashSet::from([(-1, 0, 0), (0, 0, 0), (1, 0, 0), (2, 0, 0), (3, 0, 0), (0, 1, 0), (0, 0, -1), (0, 0, 1)]);
+        assert_eq!(visible, expected);
+    }
+}
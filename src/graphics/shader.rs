@@ -5,7 +5,7 @@ use std::io::Read;
 use std::ptr;
 use bytemuck::bytes_of;
 use gl::types::*;
-use glam::{Mat4};
+use glam::{Mat4, Vec3};
 use anyhow::{Context, Result};
 
 
@@ -30,6 +30,38 @@ impl Shader {
             gl::UniformMatrix4fv(transform_loc, 1, gl::FALSE, matrix.as_ref().as_ptr());
         }
     }
+
+    pub fn uniform_float(&self, name: &str, value: f32){
+        unsafe {
+            let c_name = CString::new(name).expect("CString::new failed");
+            let location = gl::GetUniformLocation(self.id, c_name.as_ptr());
+            gl::Uniform1f(location, value);
+        }
+    }
+
+    pub fn uniform_floats(&self, name: &str, values: &[f32]){
+        unsafe {
+            let c_name = CString::new(name).expect("CString::new failed");
+            let location = gl::GetUniformLocation(self.id, c_name.as_ptr());
+            gl::Uniform1fv(location, values.len() as GLsizei, values.as_ptr());
+        }
+    }
+
+    pub fn uniform_int(&self, name: &str, value: i32){
+        unsafe {
+            let c_name = CString::new(name).expect("CString::new failed");
+            let location = gl::GetUniformLocation(self.id, c_name.as_ptr());
+            gl::Uniform1i(location, value);
+        }
+    }
+
+    pub fn uniform_vec3(&self, name: &str, value: Vec3){
+        unsafe {
+            let c_name = CString::new(name).expect("CString::new failed");
+            let location = gl::GetUniformLocation(self.id, c_name.as_ptr());
+            gl::Uniform3f(location, value.x, value.y, value.z);
+        }
+    }
 }
 
 
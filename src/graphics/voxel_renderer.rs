@@ -1,63 +1,57 @@
-use crate::graphics::mesh::Mesh;
-use crate::voxels::{Blocks, Chunk};
+use crate::graphics::occlusion::Connectivity;
+use crate::graphics::mesh::{AttrMode, AttrType, Attribute, Mesh, MeshError, VertexLayout};
+use crate::math::Vec3;
+use crate::voxels::Blocks;
+use crate::voxels::neighbourhood::Neighbourhood;
 use crate::voxels::chunk::{CHUNK_D, CHUNK_H, CHUNK_W};
 use crate::voxels::voxel::Voxel;
 
-const VERTEX_SIZE: usize = 3 +2 + 4;
-
-fn cdiv(x: isize, a: isize) -> isize {
-    if x < 0 { x / a - 1 } else { x / a }
-}
-
-fn local_neg(x: isize, size: isize) -> isize {
-    if x < 0 { size + x } else { x }
-}
-
-fn local(x: isize, size: isize) -> isize {
-    if x >= size { x - size } else { local_neg(x, size) }
+/// Упакованная вершина чанка, 12 байт: позиция угла внутри чанка 0..=16 (u8 x3), номер грани (u8),
+/// uv в тайлах (u8 x2), номер тайла атласа (u16), свет rgbs по 4 бита (u16),
+/// затенение угла соседними блоками (u8, 255 - открытый угол) и пустой байт,
+/// чтобы u16 поля каждой вершины лежали по чётному смещению
+const VERTEX_SIZE: usize = CHUNK_LAYOUT.vertex_size();
+pub const CHUNK_LAYOUT: VertexLayout = VertexLayout::new(&[
+    Attribute::new(3, AttrType::U8, AttrMode::Float),
+    Attribute::new(1, AttrType::U8, AttrMode::Integer),
+    Attribute::new(2, AttrType::U8, AttrMode::Float),
+    Attribute::new(1, AttrType::U16, AttrMode::Integer),
+    Attribute::new(1, AttrType::U16, AttrMode::Integer),
+    Attribute::new(1, AttrType::U8, AttrMode::Normalized),
+]).with_padding(1);
+
+/// Уровни затенения угла: 0 - угол зажат двумя блоками, 3 - рядом нет блоков
+const AO_LEVELS: u8 = 3;
+
+/// Размер чанка по осям x, y, z
+const CHUNK_SIZE: [isize; 3] = [CHUNK_W, CHUNK_H, CHUNK_D];
+
+/// Самый грубый уровень детализации: клетка 8x8x8 вокселей
+pub const MAX_LOD: u8 = 3;
+
+
+/// Уровень детализации чанка в `distance` чанках от камеры: до `lod_distance` полный,
+/// дальше каждое удвоение расстояния вдвое прореживает воксели
+pub fn lod_level(distance: isize, lod_distance: f32) -> u8 {
+    if lod_distance <= 0.0 || (distance as f32) < lod_distance {
+        return 0;
+    }
+    ((distance as f32 / lod_distance).log2().floor() as u8 + 1).min(MAX_LOD)
 }
 
-fn is_chunk(x: isize, y: isize, z: isize, chunks: &[Option<Chunk>]) -> bool {
-    get_chunk(x, y, z, chunks).is_some()
+fn is_chunk(x: isize, y: isize, z: isize, chunks: &Neighbourhood) -> bool {
+    chunks.chunk(x, y, z).is_some()
 }
 
-fn get_chunk(x: isize, y: isize, z: isize, chunks: &[Option<Chunk>]) -> Option<&Chunk> {
-    let index =
-        ((cdiv(y, CHUNK_H as isize) + 1) * 3 + cdiv(z, CHUNK_D as isize) + 1) * 3 +
-            cdiv(x, CHUNK_W as isize) +
-            1;
-    if index >= 0 && index < (chunks.len() as isize) {
-        chunks[index as usize].as_ref()
-    } else {
-        None
-    }
+fn light(x: isize, y: isize, z: isize, channel: usize, chunks: &Neighbourhood) -> u8 {
+    chunks.light(x, y, z, channel)
 }
 
-fn light(x: isize, y: isize, z: isize, channel: usize, chunks: &[Option<Chunk>]) -> u8 {
-    if let Some(chunk) = get_chunk(x, y, z, chunks) {
-        chunk.light_map.get(
-            local(x, CHUNK_W as isize) as usize,
-            local(y, CHUNK_H as isize) as usize,
-            local(z, CHUNK_D as isize) as usize,
-            channel
-        )
-    } else {
-        0
-    }
+fn voxel(x: isize, y: isize, z: isize, chunks: &Neighbourhood) -> Option<Voxel> {
+    chunks.voxel(x, y, z)
 }
 
-fn voxel(x: isize, y: isize, z: isize, chunks: &[Option<Chunk>]) -> Option<&Voxel> {
-    if let Some(chunk) = get_chunk(x, y, z, chunks) {
-        let lx = local(x, CHUNK_W as isize) as usize;
-        let ly = local(y, CHUNK_H as isize) as usize;
-        let lz = local(z, CHUNK_D as isize) as usize;
-        chunk.voxels.get((ly * CHUNK_D as usize + lz) * CHUNK_W as usize + lx)
-    } else {
-        None
-    }
-}
-
-fn is_blocked(x: isize, y: isize, z: isize, blocks: &Blocks, group: u8, chunks: &[Option<Chunk>]) -> bool {
+fn is_blocked(x: isize, y: isize, z: isize, blocks: &Blocks, group: u8, chunks: &Neighbourhood) -> bool {
     if !is_chunk(x, y, z, chunks) {
         return true;
     }
@@ -82,329 +76,647 @@ fn is_blocked(x: isize, y: isize, z: isize, blocks: &Blocks, group: u8, chunks:
 }
 
 
-fn vertex(
-    buffer: &mut Vec<f32>,
-    x: f32,
-    y: f32,
-    z: f32,
-    u: f32,
-    v: f32,
-    r: f32,
-    g: f32,
-    b: f32,
-    s: f32
-) {
-    buffer.push(x);
-    buffer.push(y);
-    buffer.push(z);
-    buffer.push(u);
-    buffer.push(v);
-    buffer.push(r);
-    buffer.push(g);
-    buffer.push(b);
-    buffer.push(s);
+/// Как строится меш чанка
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshingMode {
+    /// Два треугольника на каждую видимую грань
+    PerFace,
+    /// Соседние грани одного блока с одинаковым светом сливаются в большие прямоугольники
+    Greedy,
 }
 
-fn setup_uv(index: usize, uvsize: f32) -> (f32, f32, f32, f32) {
-    let u1 = ((index % 16) as f32) * uvsize;
-    let v1 = 1.0 - ((1 + index / 16) as f32) * uvsize;
-    let u2 = u1 + uvsize;
-    let v2 = v1 + uvsize;
-    (u1, v1, u2, v2)
 
+/// Грань блока: нормаль вдоль `axis` в сторону `dir`, текстура идёт по осям `u` и `v`
+struct Face {
+    axis: usize,
+    dir: isize,
+    u: usize,
+    v: usize,
+    /// u текстуры растёт против оси, чтобы снаружи грань не выглядела зеркальной
+    flip_u: bool,
 }
 
-pub struct VoxelRenderer {
-    buffer: Vec<f32>,
+
+/// Грани в порядке `Block::texture_faces`: -x, +x, -y, +y, -z, +z
+const FACES: [Face; 6] = [
+    Face { axis: 0, dir: -1, u: 2, v: 1, flip_u: false },
+    Face { axis: 0, dir: 1, u: 2, v: 1, flip_u: true },
+    Face { axis: 1, dir: -1, u: 0, v: 2, flip_u: false },
+    Face { axis: 1, dir: 1, u: 0, v: 2, flip_u: true },
+    Face { axis: 2, dir: -1, u: 0, v: 1, flip_u: true },
+    Face { axis: 2, dir: 1, u: 0, v: 1, flip_u: false },
+];
+
+/// Углы квада по осям u и v в порядке обхода
+const CORNERS: [(isize, isize); 4] = [(-1, -1), (1, -1), (1, 1), (-1, 1)];
+
+
+/// Видимая грань вокселя со сглаженным светом 0..=15 в углах по каналам rgbs
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct FaceCell {
+    block: u16,
+    tile: i32,
+    /// Грань уходит в меш второго прохода
+    translucent: bool,
+    corners: [[u8; 4]; 4],
+    ao: [u8; 4],
 }
 
-impl VoxelRenderer {
-    pub fn new(capacity: usize) -> Self {
-        Self {
-            buffer: Vec::with_capacity(capacity * VERTEX_SIZE * 6),
-        }
+
+impl FaceCell {
+    /// Свет и затенение во всех углах одинаковы, и грань можно растянуть, не искажая освещение
+    fn uniform(&self) -> bool {
+        self.corners.iter().all(|corner| *corner == self.corners[0]) && self.ao.iter().all(|&ao| ao == self.ao[0])
     }
+}
 
-    pub fn render(
-        &mut self,
-        chunk: &Chunk,
-        chunks: &Vec<Option<Chunk>>,
-        blocks: &Blocks
-    ) -> Mesh {
-        self.buffer.clear();
-
-        for y in 0_..CHUNK_H {
-            for z in 0_..CHUNK_D {
-                for x in 0..CHUNK_W {
-                    let voxel = &chunk.voxels[(y * CHUNK_D * CHUNK_W + z * CHUNK_W + x) as usize];
-                    let id = voxel.id;
 
-                    if id == 0 {
-                        continue;
-                    }
+fn offset(mut pos: [isize; 3], axis: usize, delta: isize) -> [isize; 3] {
+    pos[axis] += delta;
+    pos
+}
 
 
-                    let mut l;
-                    let uvsize = 1.0 / 16.0;
+/// Сглаженный свет в углах грани: клетка перед гранью с весом 2 и три клетки вокруг угла
+fn corner_light(pos: [isize; 3], face: &Face, chunks: &Neighbourhood) -> [[u8; 4]; 4] {
+    let front = offset(pos, face.axis, face.dir);
+    let mut corners = [[0; 4]; 4];
+    for (corner, &(du, dv)) in corners.iter_mut().zip(CORNERS.iter()) {
+        let side_u = offset(front, face.u, du);
+        let side_v = offset(front, face.v, dv);
+        let diagonal = offset(side_u, face.v, dv);
+        for (channel, value) in corner.iter_mut().enumerate() {
+            let at = |[x, y, z]: [isize; 3]| light(x, y, z, channel, chunks);
+            *value = (at(front) * 2 + at(side_u) + at(side_v) + at(diagonal) + 2) / 5;
+        }
+    }
+    corners
+}
 
-                    let (x, y, z) = (x as isize, y as isize, z as isize);
 
-                    let block = blocks.blocks.get(id as usize);
+/// Затенение углов грани по трём соседям угла перед гранью. Угол между двумя блоками
+/// затеняется полностью, даже если диагональный сосед пуст
+fn corner_ao(pos: [isize; 3], face: &Face, blocks: &Blocks, chunks: &Neighbourhood) -> [u8; 4] {
+    let solid = |[x, y, z]: [isize; 3]| {
+        voxel(x, y, z, chunks)
+            .filter(|voxel| voxel.id != 0)
+            .and_then(|voxel| blocks.get(voxel.id))
+            .is_some_and(|block| !block.light_passing)
+    };
+    let front = offset(pos, face.axis, face.dir);
+    CORNERS.map(|(du, dv)| {
+        let side_u = solid(offset(front, face.u, du));
+        let side_v = solid(offset(front, face.v, dv));
+        let diagonal = solid(offset(offset(front, face.u, du), face.v, dv));
+        if side_u && side_v {
+            0
+        } else {
+            AO_LEVELS - side_u as u8 - side_v as u8 - diagonal as u8
+        }
+    })
+}
 
-                    if let Some(Some(block)) = block {
-                        let group = block.draw_group;
-                        if !is_blocked(x,y+1,z, &blocks, group, &chunks){
-                            //l = 1.0_f32;
 
-                            let (u1, v1, u2, v2) = setup_uv(block.texture_faces[3] as usize, uvsize);
+/// Порядок вершин двух треугольников квада. Квад режется по диагонали между более светлыми углами,
+/// иначе тень от одного угла тянется полосой через всю грань
+fn triangle_order(ccw: bool, ao: [u8; 4]) -> [u32; 6] {
+    let flip = ao[0] + ao[2] < ao[1] + ao[3];
+    match (ccw, flip) {
+        (true, false) => [0, 1, 2, 0, 2, 3],
+        (true, true) => [0, 1, 3, 1, 2, 3],
+        (false, false) => [0, 2, 1, 0, 3, 2],
+        (false, true) => [0, 3, 1, 1, 3, 2],
+    }
+}
 
-                            let lr = light(x,y+1,z, 0, &chunks) as f32 / 15.0_f32;
-                            let lg = light(x,y+1,z, 1, &chunks) as f32 / 15.0_f32;
-                            let lb = light(x,y+1,z, 2, &chunks) as f32 / 15.0_f32;
-                            let ls = light(x,y+1,z, 3, &chunks) as f32 / 15.0_f32;
 
-                            let lr0 = (light(x-1,y+1,z,0, &chunks) as f32 + lr*30_f32 + light(x-1,y+1,z-1,0, &chunks) as f32 + light(x,y+1,z-1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr1 = (light(x-1,y+1,z,0, &chunks) as f32 + lr*30_f32 + light(x-1,y+1,z+1,0, &chunks) as f32 + light(x,y+1,z+1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr2 = (light(x+1,y+1,z,0, &chunks) as f32 + lr*30_f32 + light(x+1,y+1,z+1,0, &chunks) as f32 + light(x,y+1,z+1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr3 = (light(x+1,y+1,z,0, &chunks) as f32 + lr*30_f32 + light(x+1,y+1,z-1,0, &chunks) as f32 + light(x,y+1,z-1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
+/// Грань `index` вокселя в `pos`, если она не закрыта соседом той же группы отрисовки
+fn face_cell(pos: [isize; 3], index: usize, blocks: &Blocks, chunks: &Neighbourhood) -> Option<FaceCell> {
+    let voxel = chunks.center().get(pos[0] as usize, pos[1] as usize, pos[2] as usize);
+    if voxel.id == 0 {
+        return None;
+    }
+    let block = blocks.get(voxel.id)?;
+    let face = &FACES[index];
+    let [nx, ny, nz] = offset(pos, face.axis, face.dir);
+    if is_blocked(nx, ny, nz, blocks, block.draw_group, chunks) {
+        return None;
+    }
+    Some(FaceCell {
+        block: voxel.id,
+        tile: block.faces(voxel.state)[index],
+        translucent: block.translucent,
+        corners: corner_light(pos, face, chunks),
+        ao: corner_ao(pos, face, blocks, chunks),
+    })
+}
 
-                            let lg0 = (light(x-1,y+1,z,1, &chunks) as f32 + lg*30_f32 + light(x-1,y+1,z-1,1, &chunks) as f32 + light(x,y+1,z-1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg1 = (light(x-1,y+1,z,1, &chunks) as f32 + lg*30. + light(x-1,y+1,z+1,1, &chunks) as f32 + light(x,y+1,z+1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg2 = (light(x+1,y+1,z,1, &chunks) as f32 + lg*30. + light(x+1,y+1,z+1,1, &chunks) as f32 + light(x,y+1,z+1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg3 = (light(x+1,y+1,z,1, &chunks) as f32 + lg*30. + light(x+1,y+1,z-1,1, &chunks) as f32 + light(x,y+1,z-1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
 
-                            let lb0 = (light(x-1,y+1,z,2, &chunks) as f32 + lb*30. + light(x-1,y+1,z-1,2, &chunks) as f32 + light(x,y+1,z-1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb1 = (light(x-1,y+1,z,2, &chunks) as f32 + lb*30. + light(x-1,y+1,z+1,2, &chunks) as f32 + light(x,y+1,z+1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb2 = (light(x+1,y+1,z,2, &chunks) as f32 + lb*30. + light(x+1,y+1,z+1,2, &chunks) as f32 + light(x,y+1,z+1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb3 = (light(x+1,y+1,z,2, &chunks) as f32 + lb*30. + light(x+1,y+1,z-1,2, &chunks) as f32 + light(x,y+1,z-1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
+/// Квад из `width` x `height` граней, начиная с клетки `pos`. Клетка - куб из `scale` вокселей по ребру,
+/// текстура повторяется на каждом вокселе. Прозрачные грани идут в отдельный меш
+fn quad(
+    geometry: &mut ChunkGeometry,
+    index: usize,
+    pos: [isize; 3],
+    width: isize,
+    height: isize,
+    scale: isize,
+    cell: &FaceCell,
+) {
+    let face = &FACES[index];
+    let mesh = if cell.translucent { &mut geometry.translucent } else { &mut geometry.opaque };
+    let base = mesh.vertex_count() as u32;
+    for ((&(du, dv), light), ao) in CORNERS.iter().zip(cell.corners).zip(cell.ao) {
+        let along_u = if du > 0 { width } else { 0 };
+        let along_v = if dv > 0 { height } else { 0 };
+
+        let mut position = pos;
+        if face.dir > 0 {
+            position[face.axis] += 1;
+        }
+        position[face.u] += along_u;
+        position[face.v] += along_v;
+        let position = position.map(|coord| coord * scale);
+
+        let u = (if face.flip_u { width - along_u } else { along_u }) * scale;
+        let along_v = along_v * scale;
+        let light = light.iter().enumerate().fold(0u16, |packed, (channel, &l)| packed | (l as u16) << (channel * 4));
+
+        mesh.vertices.extend_from_slice(&[position[0] as u8, position[1] as u8, position[2] as u8, index as u8]);
+        mesh.vertices.extend_from_slice(&[u as u8, along_v as u8]);
+        mesh.vertices.extend_from_slice(&(cell.tile as u16).to_ne_bytes());
+        mesh.vertices.extend_from_slice(&light.to_ne_bytes());
+        mesh.vertices.extend_from_slice(&[ao * (u8::MAX / AO_LEVELS), 0]);
+    }
 
-                            let ls0 = (light(x-1,y+1,z,3, &chunks) as f32 + ls*30. + light(x-1,y+1,z-1,3, &chunks) as f32 + light(x,y+1,z-1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls1 = (light(x-1,y+1,z,3, &chunks) as f32 + ls*30. + light(x-1,y+1,z+1,3, &chunks) as f32 + light(x,y+1,z+1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls2 = (light(x+1,y+1,z,3, &chunks) as f32 + ls*30. + light(x+1,y+1,z+1,3, &chunks) as f32 + light(x,y+1,z+1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls3 = (light(x+1,y+1,z,3, &chunks) as f32 + ls*30. + light(x+1,y+1,z-1,3, &chunks) as f32 + light(x,y+1,z-1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
+    // передняя сторона - против часовой стрелки, если смотреть снаружи
+    let ccw = (if (face.u + 1) % 3 == face.v { 1 } else { -1 }) * face.dir > 0;
+    mesh.indices.extend(triangle_order(ccw, cell.ao).map(|i| base + i));
+}
 
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32+0.5_f32, z as f32-0.5_f32, u2,v1, lr0, lg0, lb0, ls0);
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32+0.5_f32, z as f32+0.5_f32, u2,v2, lr1, lg1, lb1, ls1);
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32+0.5_f32, z as f32+0.5_f32, u1,v2, lr2, lg2, lb2, ls2);
 
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32+0.5_f32, z as f32-0.5_f32, u2,v1, lr0, lg0, lb0, ls0);
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32+0.5_f32, z as f32+0.5_f32, u1,v2, lr2, lg2, lb2, ls2);
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32+0.5_f32, z as f32-0.5_f32, u1,v1, lr3, lg3, lb3, ls3);
+/// Прореженные воксели чанка для дальних мешей: клетка из `step`^3 вокселей берёт самый частый
+/// непустой блок. Вокруг чанка хранится слой клеток соседей, None - соседний чанк не загружен
+struct Downsampled {
+    step: isize,
+    /// Клеток по ребру чанка
+    size: isize,
+    cells: Vec<Option<Voxel>>,
+}
+
+
+impl Downsampled {
+    fn new(chunks: &Neighbourhood, step: isize) -> Self {
+        let size = CHUNK_W / step;
+        let mut cells = Vec::with_capacity(((size + 2) * (size + 2) * (size + 2)) as usize);
+        let mut counts: Vec<(Voxel, usize)> = Vec::new();
+        for y in -1..=size {
+            for z in -1..=size {
+                for x in -1..=size {
+                    if !is_chunk(x * step, y * step, z * step, chunks) {
+                        cells.push(None);
+                        continue;
+                    }
+                    counts.clear();
+                    for i in 0..step * step * step {
+                        let (dx, dy, dz) = (i % step, i / (step * step), i / step % step);
+                        let Some(voxel) = voxel(x * step + dx, y * step + dy, z * step + dz, chunks) else {
+                            continue;
+                        };
+                        if voxel.id == 0 {
+                            continue;
+                        }
+                        match counts.iter_mut().find(|(counted, _)| counted.id == voxel.id) {
+                            Some((_, count)) => *count += 1,
+                            None => counts.push((voxel, 1)),
                         }
                     }
+                    // при равенстве побеждает блок, встреченный первым
+                    let common = counts.iter().rev().max_by_key(|(_, count)| *count);
+                    cells.push(Some(common.map_or(Voxel::default(), |(voxel, _)| *voxel)));
+                }
+            }
+        }
+        Self { step, size, cells }
+    }
 
-                    if let Some(Some(block)) = block {
-                        let group = block.draw_group;
-                        if !is_blocked(x,y-1,z, &blocks, group, &chunks){
-                            //l = 0.75_f32;
+    /// Клетка в координатах клеток от угла чанка, -1..=size
+    fn get(&self, [x, y, z]: [isize; 3]) -> Option<Voxel> {
+        let side = self.size + 2;
+        self.cells[(((y + 1) * side + z + 1) * side + x + 1) as usize]
+    }
 
+    /// Грань клетки `pos`. Свет берётся наибольший по клетке перед гранью, без сглаживания и затенения углов.
+    /// Грани на границе чанка строятся всегда, даже против сплошного соседа: эти юбки закрывают щели
+    /// между чанками разной детализации
+    fn face_cell(&self, pos: [isize; 3], index: usize, blocks: &Blocks, chunks: &Neighbourhood) -> Option<FaceCell> {
+        let voxel = self.get(pos)?;
+        if voxel.id == 0 {
+            return None;
+        }
+        let block = blocks.get(voxel.id)?;
+        let face = &FACES[index];
+        let front = offset(pos, face.axis, face.dir);
+        let border = !(0..self.size).contains(&front[face.axis]);
+        let covered = match self.get(front) {
+            Some(neighbour) => {
+                neighbour.id != 0 && blocks.get(neighbour.id).is_some_and(|other| other.draw_group == block.draw_group)
+            }
+            None => true,
+        };
+        if covered && !border {
+            return None;
+        }
+
+        let mut light = [0u8; 4];
+        for i in 0..self.step * self.step * self.step {
+            let (dx, dy, dz) = (i % self.step, i / (self.step * self.step), i / self.step % self.step);
+            let [x, y, z] = [front[0] * self.step + dx, front[1] * self.step + dy, front[2] * self.step + dz];
+            for (channel, value) in light.iter_mut().enumerate() {
+                *value = (*value).max(chunks.light(x, y, z, channel));
+            }
+        }
+        Some(FaceCell {
+            block: voxel.id,
+            tile: block.faces(voxel.state)[index],
+            translucent: block.translucent,
+            corners: [light; 4],
+            ao: [AO_LEVELS; 4],
+        })
+    }
+}
 
-                            let (u1, v1, u2, v2) = setup_uv(block.texture_faces[2] as usize, uvsize);
 
-                            let lr = light(x,y-1,z, 0, &chunks) as f32 / 15.0_f32;
-                            let lg = light(x,y-1,z, 1, &chunks) as f32 / 15.0_f32;
-                            let lb = light(x,y-1,z, 2, &chunks) as f32 / 15.0_f32;
-                            let ls = light(x,y-1,z, 3, &chunks) as f32 / 15.0_f32;
+/// Вершины и индексы чанка, собранные на CPU. GL в них не участвует, поэтому их можно строить в рабочем потоке,
+/// а на GPU загружать уже в главном
+#[derive(Clone, Default)]
+pub struct ChunkVertices {
+    pub vertices: Vec<u8>,
+    /// По 6 индексов на квад, вершины квада идут подряд
+    pub indices: Vec<u32>,
+}
 
-                            let lr0 = (light(x-1,y-1,z-1,0, &chunks) as f32 + lr*30. + light(x-1,y-1,z,0, &chunks) as f32 + light(x,y-1,z-1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr1 = (light(x+1,y-1,z+1,0, &chunks) as f32 + lr*30. + light(x+1,y-1,z,0, &chunks) as f32 + light(x,y-1,z+1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr2 = (light(x-1,y-1,z+1,0, &chunks) as f32 + lr*30. + light(x-1,y-1,z,0, &chunks) as f32 + light(x,y-1,z+1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr3 = (light(x+1,y-1,z-1,0, &chunks) as f32 + lr*30. + light(x+1,y-1,z,0, &chunks) as f32 + light(x,y-1,z-1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
 
-                            let lg0 = (light(x-1,y-1,z-1,1, &chunks) as f32 + lg*30. + light(x-1,y-1,z,1, &chunks) as f32 + light(x,y-1,z-1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg1 = (light(x+1,y-1,z+1,1, &chunks) as f32 + lg*30. + light(x+1,y-1,z,1, &chunks) as f32 + light(x,y-1,z+1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg2 = (light(x-1,y-1,z+1,1, &chunks) as f32 + lg*30. + light(x-1,y-1,z,1, &chunks) as f32 + light(x,y-1,z+1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg3 = (light(x+1,y-1,z-1,1, &chunks) as f32 + lg*30. + light(x+1,y-1,z,1, &chunks) as f32 + light(x,y-1,z-1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
+impl ChunkVertices {
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len() / VERTEX_SIZE
+    }
 
-                            let lb0 = (light(x-1,y-1,z-1,2, &chunks) as f32 + lb*30. + light(x-1,y-1,z,2, &chunks) as f32 + light(x,y-1,z-1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb1 = (light(x+1,y-1,z+1,2, &chunks) as f32 + lb*30. + light(x+1,y-1,z,2, &chunks) as f32 + light(x,y-1,z+1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb2 = (light(x-1,y-1,z+1,2, &chunks) as f32 + lb*30. + light(x-1,y-1,z,2, &chunks) as f32 + light(x,y-1,z+1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb3 = (light(x+1,y-1,z-1,2, &chunks) as f32 + lb*30. + light(x+1,y-1,z,2, &chunks) as f32 + light(x,y-1,z-1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
+    /// Загружает вершины в меш чанка. Только в потоке с GL контекстом
+    pub fn reload(&self, mesh: &mut Mesh) -> Result<(), MeshError> {
+        mesh.reload(&self.vertices, &self.indices)
+    }
 
-                            let ls0 = (light(x-1,y-1,z-1,3, &chunks) as f32 + ls*30. + light(x-1,y-1,z,3, &chunks) as f32 + light(x,y-1,z-1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls1 = (light(x+1,y-1,z+1,3, &chunks) as f32 + ls*30. + light(x+1,y-1,z,3, &chunks) as f32 + light(x,y-1,z+1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls2 = (light(x-1,y-1,z+1,3, &chunks) as f32 + ls*30. + light(x-1,y-1,z,3, &chunks) as f32 + light(x,y-1,z+1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls3 = (light(x+1,y-1,z-1,3, &chunks) as f32 + ls*30. + light(x+1,y-1,z,3, &chunks) as f32 + light(x,y-1,z-1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
+    /// Переставляет квады так, чтобы дальние от `eye` рисовались первыми. `eye` - в координатах чанка
+    pub fn sort_back_to_front(&mut self, eye: Vec3) {
+        let position = |i: u32| {
+            let vertex = &self.vertices[i as usize * VERTEX_SIZE..];
+            Vec3::new(vertex[0] as f32, vertex[1] as f32, vertex[2] as f32)
+        };
+        let mut quads: Vec<(f32, &[u32])> = self.indices.chunks_exact(6).map(|quad| {
+            let base = *quad.iter().min().unwrap_or(&0);
+            let center = (0..4).map(|i| position(base + i)).sum::<Vec3>() / 4.0;
+            (center.distance_squared(eye), quad)
+        }).collect();
+        quads.sort_by(|a, b| b.0.total_cmp(&a.0));
+        self.indices = quads.into_iter().flat_map(|(_, quad)| quad.iter().copied()).collect();
+    }
+}
 
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32-0.5_f32, z as f32-0.5_f32, u1,v1, lr0,lg0,lb0,ls0);
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32-0.5_f32, z as f32+0.5_f32, u2,v2, lr1,lg1,lb1,ls1);
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32-0.5_f32, z as f32+0.5_f32, u1,v2, lr2,lg2,lb2,ls2);
 
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32-0.5_f32, z as f32-0.5_f32, u1,v1, lr0,lg0,lb0,ls0);
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32-0.5_f32, z as f32-0.5_f32, u2,v1, lr3,lg3,lb3,ls3);
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32-0.5_f32, z as f32+0.5_f32, u2,v2, lr1,lg1,lb1,ls1);
-                        }
-                    }
+/// Меши чанка для двух проходов: сначала непрозрачный, затем прозрачный с сортировкой граней
+#[derive(Clone, Default)]
+pub struct ChunkGeometry {
+    pub opaque: ChunkVertices,
+    pub translucent: ChunkVertices,
+    /// Какие грани чанка видны друг через друга, для отсечения чанков за рельефом
+    pub connectivity: Connectivity,
+}
 
-                    if let Some(Some(block)) = block {
-                        let group = block.draw_group;
-                        if !is_blocked(x+1,y,z, &blocks, group,&chunks){
-                            //l = 0.95_f32;
-
-                            let (u1, v1, u2, v2) = setup_uv(block.texture_faces[1] as usize, uvsize);
-
-                            let lr = light(x+1,y,z, 0, &chunks) as f32 / 15.0_f32;
-                            let lg = light(x+1,y,z, 1, &chunks) as f32 / 15.0_f32;
-                            let lb = light(x+1,y,z, 2, &chunks) as f32 / 15.0_f32;
-                            let ls = light(x+1,y,z, 3, &chunks) as f32 / 15.0_f32;
-
-                            let lr0 = (light(x+1,y-1,z-1,0, &chunks) as f32 + lr*30. + light(x+1,y,z-1,0, &chunks) as f32 + light(x+1,y-1,z,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr1 = (light(x+1,y+1,z-1,0, &chunks) as f32 + lr*30. + light(x+1,y,z-1,0, &chunks) as f32 + light(x+1,y+1,z,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr2 = (light(x+1,y+1,z+1,0, &chunks) as f32 + lr*30. + light(x+1,y,z+1,0, &chunks) as f32 + light(x+1,y+1,z,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr3 = (light(x+1,y-1,z+1,0, &chunks) as f32 + lr*30. + light(x+1,y,z+1,0, &chunks) as f32 + light(x+1,y-1,z,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            let lg0 = (light(x+1,y-1,z-1,1, &chunks) as f32 + lg*30. + light(x+1,y,z-1,1, &chunks) as f32 + light(x+1,y-1,z,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg1 = (light(x+1,y+1,z-1,1, &chunks) as f32 + lg*30. + light(x+1,y,z-1,1, &chunks) as f32 + light(x+1,y+1,z,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg2 = (light(x+1,y+1,z+1,1, &chunks) as f32 + lg*30. + light(x+1,y,z+1,1, &chunks) as f32 + light(x+1,y+1,z,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg3 = (light(x+1,y-1,z+1,1, &chunks) as f32 + lg*30. + light(x+1,y,z+1,1, &chunks) as f32 + light(x+1,y-1,z,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            let lb0 = (light(x+1,y-1,z-1,2, &chunks) as f32 + lb*30. + light(x+1,y,z-1,2, &chunks) as f32 + light(x+1,y-1,z,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb1 = (light(x+1,y+1,z-1,2, &chunks) as f32 + lb*30. + light(x+1,y,z-1,2, &chunks) as f32 + light(x+1,y+1,z,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb2 = (light(x+1,y+1,z+1,2, &chunks) as f32 + lb*30. + light(x+1,y,z+1,2, &chunks) as f32 + light(x+1,y+1,z,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb3 = (light(x+1,y-1,z+1,2, &chunks) as f32 + lb*30. + light(x+1,y,z+1,2, &chunks) as f32 + light(x+1,y-1,z,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            let ls0 = (light(x+1,y-1,z-1,3, &chunks) as f32 + ls*30. + light(x+1,y,z-1,3, &chunks) as f32 + light(x+1,y-1,z,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls1 = (light(x+1,y+1,z-1,3, &chunks) as f32 + ls*30. + light(x+1,y,z-1,3, &chunks) as f32 + light(x+1,y+1,z,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls2 = (light(x+1,y+1,z+1,3, &chunks) as f32 + ls*30. + light(x+1,y,z+1,3, &chunks) as f32 + light(x+1,y+1,z,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls3 = (light(x+1,y-1,z+1,3, &chunks) as f32 + ls*30. + light(x+1,y,z+1,3, &chunks) as f32 + light(x+1,y-1,z,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32-0.5_f32, z as f32-0.5_f32, u2,v1, lr0,lg0,lb0,ls0);
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32+0.5_f32, z as f32-0.5_f32, u2,v2, lr1,lg1,lb1,ls1);
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32+0.5_f32, z as f32+0.5_f32, u1,v2, lr2,lg2,lb2,ls2);
-
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32-0.5_f32, z as f32-0.5_f32, u2,v1, lr0,lg0,lb0,ls0);
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32+0.5_f32, z as f32+0.5_f32, u1,v2, lr2,lg2,lb2,ls2);
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32-0.5_f32, z as f32+0.5_f32, u1,v1, lr3,lg3,lb3,ls3);
-                        }
-                    }
-                    if let Some(Some(block)) = block {
-                        let group = block.draw_group;
-                        if !is_blocked(x-1,y,z, &blocks, group,&chunks){
-                            //l = 0.85_f32;
-
-                            let (u1, v1, u2, v2) = setup_uv(block.texture_faces[0] as usize, uvsize);
-
-                            let lr = light(x-1,y,z, 0, &chunks) as f32 / 15.0_f32;
-                            let lg = light(x-1,y,z, 1, &chunks) as f32 / 15.0_f32;
-                            let lb = light(x-1,y,z, 2, &chunks) as f32 / 15.0_f32;
-                            let ls = light(x-1,y,z, 3, &chunks) as f32 / 15.0_f32;
-
-                            let lr0 = (light(x-1,y-1,z-1,0, &chunks) as f32 + lr*30. + light(x-1,y,z-1,0, &chunks) as f32 + light(x-1,y-1,z,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr1 = (light(x-1,y+1,z+1,0, &chunks) as f32 + lr*30. + light(x-1,y,z+1,0, &chunks) as f32 + light(x-1,y+1,z,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr2 = (light(x-1,y+1,z-1,0, &chunks) as f32 + lr*30. + light(x-1,y,z-1,0, &chunks) as f32 + light(x-1,y+1,z,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr3 = (light(x-1,y-1,z+1,0, &chunks) as f32 + lr*30. + light(x-1,y,z+1,0, &chunks) as f32 + light(x-1,y-1,z,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            let lg0 = (light(x-1,y-1,z-1,1, &chunks) as f32 + lg*30. + light(x-1,y,z-1,1, &chunks) as f32 + light(x-1,y-1,z,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg1 = (light(x-1,y+1,z+1,1, &chunks) as f32 + lg*30. + light(x-1,y,z+1,1, &chunks) as f32 + light(x-1,y+1,z,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg2 = (light(x-1,y+1,z-1,1, &chunks) as f32 + lg*30. + light(x-1,y,z-1,1, &chunks) as f32 + light(x-1,y+1,z,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg3 = (light(x-1,y-1,z+1,1, &chunks) as f32 + lg*30. + light(x-1,y,z+1,1, &chunks) as f32 + light(x-1,y-1,z,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            let lb0 = (light(x-1,y-1,z-1,2, &chunks) as f32 + lb*30. + light(x-1,y,z-1,2, &chunks) as f32 + light(x-1,y-1,z,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb1 = (light(x-1,y+1,z+1,2, &chunks) as f32 + lb*30. + light(x-1,y,z+1,2, &chunks) as f32 + light(x-1,y+1,z,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb2 = (light(x-1,y+1,z-1,2, &chunks) as f32 + lb*30. + light(x-1,y,z-1,2, &chunks) as f32 + light(x-1,y+1,z,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb3 = (light(x-1,y-1,z+1,2, &chunks) as f32 + lb*30. + light(x-1,y,z+1,2, &chunks) as f32 + light(x-1,y-1,z,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            let ls0 = (light(x-1,y-1,z-1,3, &chunks) as f32 + ls*30. + light(x-1,y,z-1,3, &chunks) as f32 + light(x-1,y-1,z,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls1 = (light(x-1,y+1,z+1,3, &chunks) as f32 + ls*30. + light(x-1,y,z+1,3, &chunks) as f32 + light(x-1,y+1,z,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls2 = (light(x-1,y+1,z-1,3, &chunks) as f32 + ls*30. + light(x-1,y,z-1,3, &chunks) as f32 + light(x-1,y+1,z,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls3 = (light(x-1,y-1,z+1,3, &chunks) as f32 + ls*30. + light(x-1,y,z+1,3, &chunks) as f32 + light(x-1,y-1,z,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32-0.5_f32, z as f32-0.5_f32, u1,v1, lr0,lg0,lb0,ls0);
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32+0.5_f32, z as f32+0.5_f32, u2,v2, lr1,lg1,lb1,ls1);
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32+0.5_f32, z as f32-0.5_f32, u1,v2, lr2,lg2,lb2,ls2);
-
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32-0.5_f32, z as f32-0.5_f32, u1,v1, lr0,lg0,lb0,ls0);
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32-0.5_f32, z as f32+0.5_f32, u2,v1, lr3,lg3,lb3,ls3);
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32+0.5_f32, z as f32+0.5_f32, u2,v2, lr1,lg1,lb1,ls1);
+
+pub struct VoxelRenderer {
+    /// Меши строящегося чанка, `render` отдаёт их целиком
+    geometry: ChunkGeometry,
+    /// Видимые грани одного слоя чанка для жадного режима
+    mask: Vec<Option<FaceCell>>,
+}
+
+impl VoxelRenderer {
+    pub fn new() -> Self {
+        Self { geometry: ChunkGeometry::default(), mask: Vec::new() }
+    }
+
+    pub fn render(
+        &mut self,
+        chunks: &Neighbourhood,
+        blocks: &Blocks,
+        mode: MeshingMode,
+        lod: u8,
+    ) -> ChunkGeometry {
+        match (mode, lod) {
+            (MeshingMode::PerFace, 0) => self.render_faces(chunks, blocks),
+            (MeshingMode::Greedy, 0) => {
+                self.render_greedy(CHUNK_SIZE, 1, |pos, index| face_cell(pos, index, blocks, chunks))
+            }
+            _ => {
+                let cells = Downsampled::new(chunks, 1 << lod.min(MAX_LOD));
+                let size = CHUNK_SIZE.map(|size| size / cells.step);
+                self.render_greedy(size, cells.step, |pos, index| cells.face_cell(pos, index, blocks, chunks))
+            }
+        }
+        self.geometry.connectivity = Connectivity::of(chunks.center(), blocks);
+        std::mem::take(&mut self.geometry)
+    }
+
+    fn render_faces(&mut self, chunks: &Neighbourhood, blocks: &Blocks) {
+        for y in 0..CHUNK_H {
+            for z in 0..CHUNK_D {
+                for x in 0..CHUNK_W {
+                    for index in 0..FACES.len() {
+                        if let Some(cell) = face_cell([x, y, z], index, blocks, chunks) {
+                            quad(&mut self.geometry, index, [x, y, z], 1, 1, 1, &cell);
                         }
                     }
+                }
+            }
+        }
+    }
 
-                    if let Some(Some(block)) = block {
-                        let group = block.draw_group;
-                        if !is_blocked(x,y,z+1, &blocks, group,&chunks){
-                            l = 0.9_f32;
-
-                            let (u1, v1, u2, v2) = setup_uv(block.texture_faces[5] as usize, uvsize);
-
-                            let lr = light(x,y,z+1, 0, &chunks) as f32 / 15.0_f32;
-                            let lg = light(x,y,z+1, 1, &chunks) as f32 / 15.0_f32;
-                            let lb = light(x,y,z+1, 2, &chunks) as f32 / 15.0_f32;
-                            let ls = light(x,y,z+1, 3, &chunks) as f32 / 15.0_f32;
-
-                            let lr0 = l*(light(x-1,y-1,z+1,0, &chunks) as f32 + lr*30. + light(x,y-1,z+1,0, &chunks) as f32 + light(x-1,y,z+1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr1 = l*(light(x+1,y+1,z+1,0, &chunks) as f32 + lr*30. + light(x,y+1,z+1,0, &chunks) as f32 + light(x+1,y,z+1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr2 = l*(light(x-1,y+1,z+1,0, &chunks) as f32 + lr*30. + light(x,y+1,z+1,0, &chunks) as f32 + light(x-1,y,z+1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr3 = l*(light(x+1,y-1,z+1,0, &chunks) as f32 + lr*30. + light(x,y-1,z+1,0, &chunks) as f32 + light(x+1,y,z+1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            let lg0 = l*(light(x-1,y-1,z+1,1, &chunks) as f32 + lg*30. + light(x,y-1,z+1,1, &chunks) as f32 + light(x-1,y,z+1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg1 = l*(light(x+1,y+1,z+1,1, &chunks) as f32 + lg*30. + light(x,y+1,z+1,1, &chunks) as f32 + light(x+1,y,z+1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg2 = l*(light(x-1,y+1,z+1,1, &chunks) as f32 + lg*30. + light(x,y+1,z+1,1, &chunks) as f32 + light(x-1,y,z+1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg3 = l*(light(x+1,y-1,z+1,1, &chunks) as f32 + lg*30. + light(x,y-1,z+1,1, &chunks) as f32 + light(x+1,y,z+1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            let lb0 = l*(light(x-1,y-1,z+1,2, &chunks) as f32 + lb*30. + light(x,y-1,z+1,2, &chunks) as f32 + light(x-1,y,z+1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb1 = l*(light(x+1,y+1,z+1,2, &chunks) as f32 + lb*30. + light(x,y+1,z+1,2, &chunks) as f32 + light(x+1,y,z+1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb2 = l*(light(x-1,y+1,z+1,2, &chunks) as f32 + lb*30. + light(x,y+1,z+1,2, &chunks) as f32 + light(x-1,y,z+1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb3 = l*(light(x+1,y-1,z+1,2, &chunks) as f32 + lb*30. + light(x,y-1,z+1,2, &chunks) as f32 + light(x+1,y,z+1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            let ls0 = l*(light(x-1,y-1,z+1,3, &chunks) as f32 + ls*30. + light(x,y-1,z+1,3, &chunks) as f32 + light(x-1,y,z+1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls1 = l*(light(x+1,y+1,z+1,3, &chunks) as f32 + ls*30. + light(x,y+1,z+1,3, &chunks) as f32 + light(x+1,y,z+1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls2 = l*(light(x-1,y+1,z+1,3, &chunks) as f32 + ls*30. + light(x,y+1,z+1,3, &chunks) as f32 + light(x-1,y,z+1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls3 = l*(light(x+1,y-1,z+1,3, &chunks) as f32 + ls*30. + light(x,y-1,z+1,3, &chunks) as f32 + light(x+1,y,z+1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32-0.5_f32, z as f32+0.5_f32, u1,v1, lr0,lg0,lb0,ls0);
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32+0.5_f32, z as f32+0.5_f32, u2,v2, lr1,lg1,lb1,ls1);
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32+0.5_f32, z as f32+0.5_f32, u1,v2, lr2,lg2,lb2,ls2);
-
-                            vertex(&mut self.buffer, x as f32-0.5_f32, y as f32-0.5_f32, z as f32+0.5_f32, u1,v1, lr0,lg0,lb0,ls0);
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32-0.5_f32, z as f32+0.5_f32, u2,v1, lr3,lg3,lb3,ls3);
-                            vertex(&mut self.buffer, x as f32+0.5_f32, y as f32+0.5_f32, z as f32+0.5_f32, u2,v2, lr1,lg1,lb1,ls1);
-                        }
+    /// Обходит сетку клеток `size` слоями вдоль нормали каждой грани и жадно растит прямоугольники:
+    /// сначала вдоль u, затем целыми строками вдоль v. Грани с неравным светом в углах
+    /// остаются одиночными, иначе сглаживание растянулось бы на весь прямоугольник
+    fn render_greedy(
+        &mut self,
+        size: [isize; 3],
+        scale: isize,
+        face_cell: impl Fn([isize; 3], usize) -> Option<FaceCell>,
+    ) {
+        for (index, face) in FACES.iter().enumerate() {
+            let (width, height) = (size[face.u], size[face.v]);
+            let at = |layer: isize, u: isize, v: isize| {
+                let mut pos = [0; 3];
+                pos[face.axis] = layer;
+                pos[face.u] = u;
+                pos[face.v] = v;
+                pos
+            };
+
+            for layer in 0..size[face.axis] {
+                self.mask.clear();
+                for v in 0..height {
+                    for u in 0..width {
+                        self.mask.push(face_cell(at(layer, u, v), index));
                     }
-                    if let Some(Some(block)) = block {
-                        let group = block.draw_group;
-                        if !is_blocked(x,y,z-1, &blocks, group,&chunks){
-                            l = 0.8_f32;
-
-                            let (u1, v1, u2, v2) = setup_uv(block.texture_faces[4] as usize, uvsize);
-
-                            let lr = light(x,y,z-1, 0, &chunks) as f32 / 15.0_f32;
-                            let lg = light(x,y,z-1, 1, &chunks) as f32 / 15.0_f32;
-                            let lb = light(x,y,z-1, 2, &chunks) as f32 / 15.0_f32;
-                            let ls = light(x,y,z-1, 3, &chunks) as f32 / 15.0_f32;
-
-                            let lr0 = l*(light(x-1,y-1,z-1,0, &chunks) as f32 + lr*30_f32 + light(x,y-1,z-1,0, &chunks) as f32 + light(x-1,y,z-1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr1 = l*(light(x-1,y+1,z-1,0, &chunks) as f32 + lr*30_f32 + light(x,y+1,z-1,0, &chunks) as f32 + light(x-1,y,z-1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr2 = l*(light(x+1,y+1,z-1,0, &chunks) as f32 + lr*30_f32 + light(x,y+1,z-1,0, &chunks) as f32 + light(x+1,y,z-1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lr3 = l*(light(x+1,y-1,z-1,0, &chunks) as f32 + lr*30_f32 + light(x,y-1,z-1,0, &chunks) as f32 + light(x+1,y,z-1,0, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            let lg0 = l*(light(x-1,y-1,z-1,1, &chunks) as f32 + lg*30_f32 + light(x,y-1,z-1,1, &chunks) as f32 + light(x-1,y,z-1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg1 = l*(light(x-1,y+1,z-1,1, &chunks) as f32 + lg*30_f32 + light(x,y+1,z-1,1, &chunks) as f32 + light(x-1,y,z-1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg2 = l*(light(x+1,y+1,z-1,1, &chunks) as f32 + lg*30_f32 + light(x,y+1,z-1,1, &chunks) as f32 + light(x+1,y,z-1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lg3 = l*(light(x+1,y-1,z-1,1, &chunks) as f32 + lg*30_f32 + light(x,y-1,z-1,1, &chunks) as f32 + light(x+1,y,z-1,1, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            let lb0 = l*(light(x-1,y-1,z-1,2, &chunks) as f32 + lb*30_f32 + light(x,y-1,z-1,2, &chunks) as f32 + light(x-1,y,z-1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb1 = l*(light(x-1,y+1,z-1,2, &chunks) as f32 + lb*30_f32 + light(x,y+1,z-1,2, &chunks) as f32 + light(x-1,y,z-1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb2 = l*(light(x+1,y+1,z-1,2, &chunks) as f32 + lb*30_f32 + light(x,y+1,z-1,2, &chunks) as f32 + light(x+1,y,z-1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let lb3 = l*(light(x+1,y-1,z-1,2, &chunks) as f32 + lb*30_f32 + light(x,y-1,z-1,2, &chunks) as f32 + light(x+1,y,z-1,2, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            let ls0 = l*(light(x-1,y-1,z-1,3, &chunks) as f32 + ls*30_f32 + light(x,y-1,z-1,3, &chunks) as f32 + light(x-1,y,z-1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls1 = l*(light(x-1,y+1,z-1,3, &chunks) as f32 + ls*30_f32 + light(x,y+1,z-1,3, &chunks) as f32 + light(x-1,y,z-1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls2 = l*(light(x+1,y+1,z-1,3, &chunks) as f32 + ls*30_f32 + light(x,y+1,z-1,3, &chunks) as f32 + light(x+1,y,z-1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-                            let ls3 = l*(light(x+1,y-1,z-1,3, &chunks) as f32 + ls*30_f32  + light(x,y-1,z-1,3, &chunks) as f32 + light(x+1,y,z-1,3, &chunks) as f32) / 5.0_f32 / 15.0_f32;
-
-                            vertex(&mut self.buffer, x as f32 -0.5_f32, y as f32-0.5_f32, z as f32-0.5_f32, u2,v1, lr0,lg0,lb0,ls0);
-                            vertex(&mut self.buffer, x as f32 -0.5_f32, y as f32+0.5_f32, z as f32-0.5_f32, u2,v2, lr1,lg1,lb1,ls1);
-                            vertex(&mut self.buffer, x as f32 +0.5_f32, y as f32+0.5_f32, z as f32-0.5_f32, u1,v2, lr2,lg2,lb2,ls2);
-
-                            vertex(&mut self.buffer, x as f32 -0.5_f32, y as f32-0.5_f32, z as f32-0.5_f32, u2,v1, lr0,lg0,lb0,ls0);
-                            vertex(&mut self.buffer, x as f32 +0.5_f32, y as f32 +0.5_f32, z as f32-0.5_f32, u1,v2, lr2,lg2,lb2,ls2);
-                            vertex(&mut self.buffer, x as f32 +0.5_f32, y as f32 -0.5_f32, z as f32-0.5_f32, u1,v1, lr3,lg3,lb3,ls3);
+                }
+                let mask = &mut self.mask;
+                let cell_at = |mask: &[Option<FaceCell>], u: isize, v: isize| mask[(v * width + u) as usize];
+
+                for v in 0..height {
+                    let mut u = 0;
+                    while u < width {
+                        let Some(cell) = cell_at(mask, u, v) else {
+                            u += 1;
+                            continue;
+                        };
+
+                        let (mut w, mut h) = (1, 1);
+                        if cell.uniform() {
+                            while u + w < width && cell_at(mask, u + w, v) == Some(cell) {
+                                w += 1;
+                            }
+                            while v + h < height && (u..u + w).all(|i| cell_at(mask, i, v + h) == Some(cell)) {
+                                h += 1;
+                            }
                         }
+                        for dv in 0..h {
+                            for du in 0..w {
+                                mask[((v + dv) * width + u + du) as usize] = None;
+                            }
+                        }
+
+                        quad(&mut self.geometry, index, at(layer, u, v), w, h, scale, &cell);
+                        u += w;
                     }
                 }
             }
         }
+    }
+}
 
-        Mesh::new(self.buffer.as_ptr(), self.buffer.len() / VERTEX_SIZE, [3, 2, 4, 0].as_ptr())
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::GeneratorKind;
+    use crate::lighting::Lighting;
+    use crate::test_util::{self, loaded_world, settings};
+    use crate::voxels::chunk::{Chunk, CHUNK_VOL};
+    use crate::voxels::Block;
+
+    fn blocks() -> Blocks {
+        let mut blocks = Blocks::init();
+        blocks.add(Block::new(0, "air", 0));
+        blocks.add(Block::new(1, "stone", 2));
+        blocks.add(Block::new(2, "dirt", 9));
+        let mut glass = Block::new(3, "glass", 4);
+        glass.draw_group = 2;
+        glass.light_passing = true;
+        glass.translucent = true;
+        blocks.add(glass);
+        let mut leaves = Block::new(4, "leaves", 5);
+        leaves.draw_group = 3;
+        leaves.light_passing = true;
+        blocks.add(leaves);
+        blocks
+    }
+
+    /// Слой y = 0 из блоков `block(x, z)`, соседних чанков нет
+    fn layer(block: impl Fn(isize, isize) -> u16) -> Chunk {
+        let mut voxels = vec![Voxel::default(); CHUNK_VOL];
+        for z in 0..CHUNK_D {
+            for x in 0..CHUNK_W {
+                voxels[Chunk::index(x as usize, 0, z as usize)].id = block(x, z);
+            }
+        }
+        Chunk::from_voxels(0, 0, 0, &voxels)
+    }
+
+    fn render(chunk: &Chunk, mode: MeshingMode) -> ChunkVertices {
+        render_lod(chunk, mode, 0).opaque
     }
-}
\ No newline at end of file
+
+    fn render_lod(chunk: &Chunk, mode: MeshingMode, lod: u8) -> ChunkGeometry {
+        let mut chunks = [None; 27];
+        chunks[Neighbourhood::index(0, 0, 0)] = Some(chunk);
+        VoxelRenderer::new().render(&Neighbourhood::new(chunks), &blocks(), mode, lod)
+    }
+
+    /// Суммарная площадь треугольников меша
+    fn area(vertices: &ChunkVertices) -> f32 {
+        let position = |i: u32| {
+            let vertex = &vertices.vertices[i as usize * VERTEX_SIZE..];
+            Vec3::new(vertex[0] as f32, vertex[1] as f32, vertex[2] as f32)
+        };
+        vertices.indices.chunks(3).map(|triangle| {
+            let [a, b, c] = [0, 1, 2].map(|i| position(triangle[i]));
+            (b - a).cross(c - a).length() / 2.0
+        }).sum()
+    }
+
+    #[test]
+    fn greedy_merges_faces_of_one_block() {
+        let chunk = layer(|_, _| 1);
+        assert_eq!(render(&chunk, MeshingMode::PerFace).indices.len() / 3, 2 * 256);
+        assert_eq!(render(&chunk, MeshingMode::Greedy).indices.len() / 3, 2);
+
+        let chunk = layer(|x, _| if x < 8 { 1 } else { 2 });
+        assert_eq!(render(&chunk, MeshingMode::Greedy).indices.len() / 3, 2 * 2);
+    }
+
+    #[test]
+    fn greedy_keeps_faces_with_smoothed_light() {
+        let mut chunk = layer(|_, _| 1);
+        for z in 0..CHUNK_D as usize {
+            for x in 0..CHUNK_W as usize {
+                chunk.light_map.set(x, 1, z, 3, 15);
+            }
+        }
+        let per_face = render(&chunk, MeshingMode::PerFace);
+        let greedy = render(&chunk, MeshingMode::Greedy);
+
+        // у краёв свет сглаживается с темнотой за границей чанка, середина 14x14 сливается
+        assert_eq!(greedy.indices.len() / 3, 2 * (60 + 1));
+        assert!((area(&per_face) - 256.0).abs() < 1e-3);
+        assert!((area(&greedy) - 256.0).abs() < 1e-3);
+    }
+
+    /// Свет rgbs каждой вершины, распакованный из 4-битных каналов
+    fn vertex_lights(vertices: &ChunkVertices) -> Vec<[u8; 4]> {
+        vertices.vertices.chunks(VERTEX_SIZE).map(|vertex| {
+            let packed = u16::from_ne_bytes([vertex[8], vertex[9]]);
+            [0, 1, 2, 3].map(|channel| (packed >> (channel * 4) & 0xf) as u8)
+        }).collect()
+    }
+
+    #[test]
+    fn vertex_fields_stay_aligned() {
+        // u16 поля лежат со смещений 6 и 8, размер вершины сохраняет их чётными у каждой вершины
+        assert_eq!(VERTEX_SIZE, 12);
+        assert_eq!(CHUNK_LAYOUT.attributes[..3].iter().map(Attribute::bytes).sum::<usize>(), 6);
+    }
+
+    #[test]
+    fn lamp_lights_its_faces() {
+        let blocks = test_util::blocks();
+        let mut chunks = loaded_world(&blocks, &settings(1, GeneratorKind::Empty), (0, 0, 0));
+        let mut lighting = Lighting::init();
+        lighting.on_world_loaded(&blocks, &mut chunks);
+
+        chunks.set(8, 8, 8, 3, &blocks).unwrap();
+        lighting.on_block_set(8, 8, 8, &blocks, &mut chunks);
+
+        let snapshot = chunks.snapshot(0, 0, 0);
+        let neighbourhood = Neighbourhood::from_snapshot(&snapshot);
+        let vertices = VoxelRenderer::new().render(&neighbourhood, &blocks, MeshingMode::PerFace, 0).opaque;
+        // в пустом чанке только лампа: 6 граней по 4 вершины
+        assert_eq!(vertices.vertex_count(), 24);
+        // перед гранью красный 9, рядом с углом 8, 8 и 7 по диагонали: (2 * 9 + 8 + 8 + 7) / 5
+        for light in vertex_lights(&vertices) {
+            assert_eq!(light[..3], [8, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn corners_next_to_blocks_are_occluded() {
+        let mut chunk = layer(|_, _| 1);
+        chunk.set(8, 1, 8, Voxel { id: 1, state: 0 });
+        chunk.set(9, 1, 7, Voxel { id: 1, state: 0 });
+        let vertices = render(&chunk, MeshingMode::PerFace);
+
+        // верхние грани пола: угол (8, 1, 8) касается одного блока, угол (9, 1, 8) зажат между двумя
+        let top_ao = |corner: [u8; 3]| -> Vec<u8> {
+            vertices.vertices.chunks(VERTEX_SIZE)
+                .filter(|vertex| vertex[..3] == corner && vertex[3] == 3)
+                .map(|vertex| vertex[10])
+                .collect()
+        };
+        assert_eq!(top_ao([8, 1, 8]), vec![170; 3]);
+        assert_eq!(top_ao([9, 1, 8]), vec![0; 2]);
+        assert_eq!(top_ao([2, 1, 2]), vec![255; 4]);
+    }
+
+    #[test]
+    fn quads_split_between_lighter_corners() {
+        for ccw in [true, false] {
+            let order = triangle_order(ccw, [0, 3, 3, 3]);
+            // тёмный угол 0 входит только в один треугольник
+            assert_eq!(order.iter().filter(|&&i| i == 0).count(), 1);
+            assert_eq!(triangle_order(ccw, [3; 4]).iter().filter(|&&i| i == 0).count(), 2);
+        }
+    }
+
+    #[test]
+    fn distant_chunks_are_downsampled() {
+        let mut chunks = [None; 27];
+        let mut chunk = layer(|x, _| if x == 0 { 2 } else { 1 });
+        chunk.set(5, 9, 5, Voxel { id: 2, state: 0 });
+        chunks[Neighbourhood::index(0, 0, 0)] = Some(&chunk);
+        let cells = Downsampled::new(&Neighbourhood::new(chunks), 2);
+
+        // в клетке 2x2x2 воздух не считается: слой толщиной в воксель остаётся сплошным
+        assert_eq!(cells.get([3, 0, 3]).map(|voxel| voxel.id), Some(1));
+        assert_eq!(cells.get([0, 0, 3]).map(|voxel| voxel.id), Some(2));
+        assert_eq!(cells.get([2, 4, 2]).map(|voxel| voxel.id), Some(2));
+        assert_eq!(cells.get([3, 1, 3]).map(|voxel| voxel.id), Some(0));
+        assert_eq!(cells.get([-1, 0, 3]), None);
+
+        // верх и низ слоя - по квадру, юбки по краям чанка - по квадру на сторону
+        let lod = render_lod(&layer(|_, _| 1), MeshingMode::Greedy, 2).opaque;
+        assert_eq!(lod.indices.len() / 3, 2 * 6);
+        let top = lod.vertices.chunks(VERTEX_SIZE).map(|vertex| vertex[1]).max();
+        assert_eq!(top, Some(4));
+    }
+
+    #[test]
+    fn lod_grows_with_distance() {
+        let levels: Vec<u8> = (0..20).map(|distance| lod_level(distance, 3.0)).collect();
+        assert_eq!(levels[..6], [0, 0, 0, 1, 1, 1]);
+        assert_eq!(levels[6..], [2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 3]);
+        assert_eq!(lod_level(100, 0.0), 0);
+    }
+
+    #[test]
+    fn leaves_stay_in_the_opaque_pass() {
+        let geometry = render_lod(&layer(|x, _| if x == 3 { 4 } else { 1 }), MeshingMode::Greedy, 0);
+        assert!(geometry.translucent.indices.is_empty());
+        assert!(geometry.opaque.vertices.chunks(VERTEX_SIZE).any(|vertex| vertex[6] == 5));
+    }
+
+    #[test]
+    fn translucent_faces_are_sorted_back_to_front() {
+        let geometry = render_lod(&layer(|x, _| if x == 3 || x == 12 { 3 } else { 1 }), MeshingMode::Greedy, 0);
+        // стекло не закрывает соседние грани камня, а его грани уходят в свой меш
+        assert!(geometry.opaque.vertices.chunks(VERTEX_SIZE).all(|vertex| vertex[6] == 2));
+        assert!(geometry.translucent.vertices.chunks(VERTEX_SIZE).all(|vertex| vertex[6] == 4));
+        assert!(geometry.opaque.indices.len() / 6 > 2);
+
+        let centers = |mesh: &ChunkVertices| -> Vec<Vec3> {
+            mesh.indices.chunks(6).map(|quad| {
+                let base = *quad.iter().min().unwrap() as usize;
+                (0..4).map(|i| {
+                    let vertex = &mesh.vertices[(base + i) * VERTEX_SIZE..];
+                    Vec3::new(vertex[0] as f32, vertex[1] as f32, vertex[2] as f32)
+                }).sum::<Vec3>() / 4.0
+            }).collect()
+        };
+        let mut translucent = geometry.translucent;
+        for eye in [Vec3::new(-10.0, 0.5, 8.0), Vec3::new(30.0, 0.5, 8.0)] {
+            translucent.sort_back_to_front(eye);
+            let centers = centers(&translucent);
+            let distances: Vec<f32> = centers.iter().map(|center| center.distance(eye)).collect();
+            assert!(distances.windows(2).all(|pair| pair[0] >= pair[1]));
+            // первыми идут грани дальнего столбика стекла
+            assert!((centers[0].x - 8.0).signum() != (eye.x - 8.0).signum());
+        }
+    }
+}
@@ -61,11 +61,6 @@ impl LightMap {
         self.map[index] = (self.map[index] & 0xF0FF) | ((value as u16) << 8);
     }
 
-    pub fn set_s(&mut self, x: usize, y: usize, z: usize, value: u8) {
-        let index = y * CHUNK_D as usize * CHUNK_W as usize + z * CHUNK_W as usize + x;
-        self.map[index] = (self.map[index] & 0x0FFF) | ((value as u16) << 12);
-    }
-
     pub fn set(&mut self, x: usize, y: usize, z: usize, channel: usize, value: u8) {
         let index = y * CHUNK_D as usize * CHUNK_W as usize + z * CHUNK_W as usize + x;
         self.map[index] = (self.map[index] & (0xFFFF & (!(0xF << (channel * 4))))) | ((value as u16) << (channel << 2));
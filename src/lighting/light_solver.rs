@@ -1,5 +1,5 @@
 use std::collections::VecDeque;
-use crate::voxels::{chunk::{CHUNK_D, CHUNK_H, CHUNK_W}, chunks::Chunks, Blocks};
+use crate::voxels::{chunks::Chunks, Blocks};
 
 #[derive(Clone, Copy)]
 struct LightEntry {
@@ -45,13 +45,8 @@ impl LightSolver {
 
             if let Some(chunk) = chunk {
                 chunk.modified = true;
-                chunk.light_map.set(
-                    (entry.x as usize) - (chunk.x as usize) * CHUNK_W as usize,
-                    (entry.y as usize) - (chunk.y as usize) * CHUNK_H as usize,
-                    (entry.z as usize) - (chunk.z as usize) * CHUNK_D as usize,
-                    self.channel as usize,
-                    entry.light
-                );
+                let (lx, ly, lz) = Chunks::local_pos(entry.x as isize, entry.y as isize, entry.z as isize);
+                chunk.light_map.set(lx, ly, lz, self.channel as usize, entry.light);
             }
         } else {
             self.add(x as i32,y as i32,z as i32, Some(chunks.get_light(x as isize,y as isize,z as isize, self.channel as usize) as i32), chunks);
@@ -71,12 +66,8 @@ impl LightSolver {
     pub fn remove(&mut self, x: isize, y: isize, z: isize, chunks: &mut Chunks) {
         let chunk = chunks.get_mut_chunk_by_voxel(x, y, z);
         if let Some(chunk) = chunk {
-            let light = chunk.light_map.get(
-                (x - chunk.x * (CHUNK_W as isize)) as usize,
-                (y - chunk.y * (CHUNK_H as isize)) as usize,
-                (z - chunk.z * (CHUNK_D as isize)) as usize,
-                self.channel as usize
-            );
+            let (lx, ly, lz) = Chunks::local_pos(x, y, z);
+            let light = chunk.light_map.get(lx, ly, lz, self.channel as usize);
             if light == 0 {
                 return;
             }
@@ -87,13 +78,7 @@ impl LightSolver {
                 light,
             };
             self.rem_queue.push_back(entry);
-            chunk.light_map.set(
-                (x - chunk.x * (CHUNK_W as isize)) as usize,
-                (y - chunk.y * (CHUNK_H as isize)) as usize,
-                (z - chunk.z * (CHUNK_D as isize)) as usize,
-                self.channel as usize,
-                0
-            );
+            chunk.light_map.set(lx, ly, lz, self.channel as usize, 0);
         }
     }
 
@@ -121,13 +106,8 @@ impl LightSolver {
                     if light != 0 && light == entry.light - 1 {
                         let nentry = LightEntry { x, y, z, light };
                         self.rem_queue.push_back(nentry);
-                        chunk.light_map.set(
-                            (x as usize) - (chunk.x as usize) * CHUNK_W as usize,
-                            (y as usize) - (chunk.y as usize) * CHUNK_H as usize,
-                            (z as usize) - (chunk.z as usize) * CHUNK_D as usize,
-                            self.channel as usize,
-                            0
-                        );
+                        let (lx, ly, lz) = Chunks::local_pos(x as isize, y as isize, z as isize);
+                        chunk.light_map.set(lx, ly, lz, self.channel as usize, 0);
                         chunk.modified = true;
                     } else if light >= entry.light {
                         let nentry = LightEntry { x, y, z, light };
@@ -151,7 +131,7 @@ impl LightSolver {
                     z as isize,
                     self.channel as usize
                 );
-                let v = chunks.get_voxel(x as isize, y as isize, z as isize).cloned();
+                let v = chunks.get_voxel(x as isize, y as isize, z as isize);
                 if
                 let Some(chunk) = chunks.get_mut_chunk_by_voxel(
                     x as isize,
@@ -162,13 +142,8 @@ impl LightSolver {
                     if let Some(v) = v {
                         if let Some(block) = blocks.get(v.id){
                             if block.light_passing && light + 2 <= entry.light {
-                                chunk.light_map.set(
-                                    (x as usize) - (chunk.x as usize) * CHUNK_W as usize,
-                                    (y as usize) - (chunk.y as usize) * CHUNK_H as usize,
-                                    (z as usize) - (chunk.z as usize) * CHUNK_D as usize,
-                                    self.channel as usize,
-                                    entry.light - 1
-                                );
+                                let (lx, ly, lz) = Chunks::local_pos(x as isize, y as isize, z as isize);
+                                chunk.light_map.set(lx, ly, lz, self.channel as usize, entry.light - 1);
                                 chunk.modified = true;
                                 let nentry = LightEntry {
                                     x,
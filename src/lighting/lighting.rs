@@ -1,4 +1,4 @@
-use noise::NoiseFn;
+use std::sync::Arc;
 use crate::lighting::LightSolver;
 use crate::voxels::chunk::{CHUNK_D, CHUNK_H, CHUNK_VOL, CHUNK_W};
 use crate::voxels::{Blocks, Chunks};
@@ -28,97 +28,96 @@ impl Lighting {
 
 
     pub fn clear(&mut self, chunks: &mut Chunks) {
-        for y in 0..chunks.h {
-            for z in 0..chunks.d {
-                for x in 0..chunks.w {
-                    match chunks.get_mut_chunk(x as isize, y as isize, z as isize) {
-                        Some(chunk) => {
-                            for i in 0..CHUNK_VOL {
-                                chunk.light_map.map[i] = 0;
-                            }
-                        }
-
-                        None => {
-                            // значит чанка нету
-                        }
-                    }
-                }
+        for chunk in chunks.chunks.values_mut().map(Arc::make_mut) {
+            for i in 0..CHUNK_VOL {
+                chunk.light_map.map[i] = 0;
             }
         }
-
     }
 
 
     pub fn on_world_loaded(&mut self, blocks: &Blocks, chunks: &mut Chunks) {
-        let h = chunks.h;
-        let d = chunks.d;
-        let w = chunks.w;
-
-        for y in 0..h * CHUNK_H as usize {
-            for z in 0..d * CHUNK_D as usize {
-                for x in 0..w * CHUNK_W as usize {
-                    let vox = chunks.get_voxel(x as isize, y as isize, z as isize);
-                    if let Some(vox) = vox {
-                        if vox.id == 3 {
-                            self.solver_r.add(x as i32, y as i32, z as i32, Some(15), chunks);
-                            self.solver_g.add(x as i32, y as i32, z as i32, Some(15), chunks);
-                            self.solver_b.add(x as i32, y as i32, z as i32, Some(15), chunks);
+        let mut keys: Vec<_> = chunks.chunks.keys().copied().collect();
+        // сверху вниз, чтобы небесный свет приходил из уже освещённых чанков
+        keys.sort_by_key(|&(_, y, _)| -y);
+
+        for (cx, cy, cz) in keys {
+            self.on_chunk_loaded(cx, cy, cz, blocks, chunks);
+        }
+    }
+
+
+    /// Освещает только что загруженный чанк и подтягивает в него свет соседей
+    pub fn on_chunk_loaded(&mut self, cx: isize, cy: isize, cz: isize, blocks: &Blocks, chunks: &mut Chunks) {
+        let (ox, oy, oz) = (cx * CHUNK_W, cy * CHUNK_H, cz * CHUNK_D);
+
+        for ly in 0..CHUNK_H {
+            for lz in 0..CHUNK_D {
+                for lx in 0..CHUNK_W {
+                    let (x, y, z) = (ox + lx, oy + ly, oz + lz);
+                    let vox = match chunks.get_voxel(x, y, z) {
+                        Some(vox) => vox,
+                        None => return,
+                    };
+                    if let Some(block) = blocks.get(vox.id) {
+                        let emission = block.emission_at(vox.state);
+                        if emission != [0; 3] {
+                            let (x, y, z) = (x as i32, y as i32, z as i32);
+                            self.solver_r.add(x, y, z, Some(emission[0] as i32), chunks);
+                            self.solver_g.add(x, y, z, Some(emission[1] as i32), chunks);
+                            self.solver_b.add(x, y, z, Some(emission[2] as i32), chunks);
                         }
                     }
                 }
             }
         }
 
-        for z in 0..d * CHUNK_D as usize {
-            for x in 0..w * CHUNK_W as usize {
-                for y in 0..h * CHUNK_H as usize {
-                    let vox = chunks.get_voxel(x as isize, y as isize, z as isize);
-                    if let Some(vox) = vox {
-                        if vox.id != 0 {
-                            break;
-                        }
-                        let voxel = chunks.get_mut_chunk_by_voxel(
-                            x as isize,
-                            y as isize,
-                            z as isize
-                        );
-                        if let Some(voxel) = voxel {
-                            voxel.light_map.set_s(x % CHUNK_W as usize, y % CHUNK_H as usize, z % CHUNK_D as usize, 0xf);
-                        }
+        // без загруженного соседа сверху столб открыт небу, если он выше поверхности генератора
+        let above_loaded = chunks.get_chunk(cx, cy + 1, cz).is_some();
+        for lz in 0..CHUNK_D {
+            for lx in 0..CHUNK_W {
+                let (x, z) = (ox + lx, oz + lz);
+                if above_loaded {
+                    if chunks.get_light(x, oy + CHUNK_H, z, 3) != 0xf {
+                        continue;
+                    }
+                } else if let Some(surface) = chunks.generator.surface_height(x, z) {
+                    if surface >= oy + CHUNK_H {
+                        continue;
+                    }
+                }
+                // столб продолжается и в уже загруженные чанки ниже
+                let mut y = oy + CHUNK_H - 1;
+                while let Some(vox) = chunks.get_voxel(x, y, z) {
+                    if vox.id != 0 || (y < oy && chunks.get_light(x, y, z, 3) == 0xf) {
+                        break;
                     }
+                    self.solver_s.add(x as i32, y as i32, z as i32, Some(0xf), chunks);
+                    y -= 1;
                 }
             }
         }
 
-        for z in 0..d * CHUNK_D as usize {
-            for x in 0..w * CHUNK_W as usize {
-                for y in (0..=h * CHUNK_H as usize - 1).rev() {
-                    let vox = chunks.get_voxel(x as isize, y as isize, z as isize);
-                    if let Some(vox) = vox {
-                        if vox.id != 0 {
-                            break;
-                        }
-
-                        if
-                        chunks.get_light((x as isize) - 1, y as isize, z as isize, 3) == 0 ||
-                            chunks.get_light((x as isize) + 1, y as isize, z as isize, 3) == 0 ||
-                            chunks.get_light(x as isize, (y as isize) - 1, z as isize, 3) == 0 ||
-                            chunks.get_light(x as isize, (y as isize) + 1, z as isize, 3) == 0 ||
-                            chunks.get_light(x as isize, y as isize, (z as isize) - 1, 3) == 0 ||
-                            chunks.get_light(x as isize, y as isize, (z as isize) + 1, 3) == 0
-                        {
-                            self.solver_s.add(x as i32, y as i32, z as i32, None, chunks);
-                        }
-                        if
-                        let Some(voxel) = chunks.get_mut_chunk_by_voxel(
-                            x as isize,
-                            y as isize,
-                            z as isize
-                        )
-                        {
-                            voxel.light_map.set_s(x % CHUNK_W as usize, y % CHUNK_H as usize, z % CHUNK_D as usize, 0xf);
-                        }
-                    }
+        for (nx, ny, nz) in [(-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0), (0, 0, -1), (0, 0, 1)] {
+            if chunks.get_chunk(cx + nx, cy + ny, cz + nz).is_none() {
+                continue;
+            }
+            for a in 0..CHUNK_W {
+                for b in 0..CHUNK_W {
+                    // воксели соседа, прилегающие к грани нового чанка
+                    let (x, y, z) = match (nx, ny, nz) {
+                        (-1, _, _) => (ox - 1, oy + a, oz + b),
+                        (1, _, _) => (ox + CHUNK_W, oy + a, oz + b),
+                        (_, -1, _) => (ox + a, oy - 1, oz + b),
+                        (_, 1, _) => (ox + a, oy + CHUNK_H, oz + b),
+                        (_, _, -1) => (ox + a, oy + b, oz - 1),
+                        _ => (ox + a, oy + b, oz + CHUNK_D),
+                    };
+                    let (x, y, z) = (x as i32, y as i32, z as i32);
+                    self.solver_r.add(x, y, z, None, chunks);
+                    self.solver_g.add(x, y, z, None, chunks);
+                    self.solver_b.add(x, y, z, None, chunks);
+                    self.solver_s.add(x, y, z, None, chunks);
                 }
             }
         }
@@ -130,16 +129,21 @@ impl Lighting {
     }
 
 
+    /// Пересчитывает свет вокруг вокселя, который уже записан в `chunks`
     pub fn on_block_set(
         &mut self,
         x: isize,
         y: isize,
         z: isize,
-        id: u8,
         blocks: &Blocks,
         chunks: &mut Chunks
     ) {
-        if id == 0 {
+        let voxel = match chunks.get_voxel(x, y, z) {
+            Some(voxel) => voxel,
+            None => return,
+        };
+
+        if voxel.id == 0 {
             self.solver_r.remove(x, y, z, chunks);
             self.solver_g.remove(x, y, z, chunks);
             self.solver_b.remove(x, y, z, chunks);
@@ -149,14 +153,13 @@ impl Lighting {
             self.solver_b.solve(&blocks, chunks);
 
             if chunks.get_light(x, y + 1, z, 3) == 0xf {
-                for i in (0..=y).rev() {
-                    let voxel = chunks.get_voxel(x, i, z);
-                    if let Some(voxel) = voxel {
-                        if voxel.id != 0 {
-                            break;
-                        }
-                        self.solver_s.add(x as i32, i as i32, z as i32, Some(0xf), chunks);
+                let mut i = y;
+                while let Some(voxel) = chunks.get_voxel(x, i, z) {
+                    if voxel.id != 0 {
+                        break;
                     }
+                    self.solver_s.add(x as i32, i as i32, z as i32, Some(0xf), chunks);
+                    i -= 1;
                 }
             }
 
@@ -197,12 +200,12 @@ impl Lighting {
             self.solver_b.remove(x, y, z, chunks);
             self.solver_s.remove(x, y, z, chunks);
 
-            for i in (0..=y - 1).rev() {
+            let mut i = y - 1;
+            while chunks.get_voxel(x, i, z).is_some() {
                 self.solver_s.remove(x, i, z, chunks);
-                if let Some(voxel) = chunks.get_voxel(x, i - 1, z) {
-                    if i == 0 || voxel.id != 0 {
-                        break;
-                    }
+                match chunks.get_voxel(x, i - 1, z) {
+                    Some(voxel) if voxel.id == 0 => i -= 1,
+                    _ => break,
                 }
             }
 
@@ -211,14 +214,15 @@ impl Lighting {
             self.solver_b.solve(&blocks, chunks);
             self.solver_s.solve(&blocks, chunks);
 
-            let block = blocks.get(id);
+            let block = blocks.get(voxel.id);
 
             if let Some(block) = block {
-                if block.emission[0] != 0 || block.emission[0] != 0 || block.emission[0] != 0 {
+                let emission = block.emission_at(voxel.state);
+                if emission != [0; 3] {
                     let (x, y, z) = (x as i32, y as i32, z as i32);
-                    self.solver_r.add(x, y, z, Some(block.emission[0] as i32), chunks);
-                    self.solver_g.add(x, y, z, Some(block.emission[1] as i32), chunks);
-                    self.solver_b.add(x, y, z, Some(block.emission[2] as i32), chunks);
+                    self.solver_r.add(x, y, z, Some(emission[0] as i32), chunks);
+                    self.solver_g.add(x, y, z, Some(emission[1] as i32), chunks);
+                    self.solver_b.add(x, y, z, Some(emission[2] as i32), chunks);
 
                     self.solver_r.solve(&blocks, chunks);
                     self.solver_g.solve(&blocks, chunks);
@@ -227,4 +231,37 @@ impl Lighting {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::GeneratorKind;
+    use crate::test_util::{blocks, loaded_world, settings};
+
+    #[test]
+    fn caves_under_unloaded_terrain_stay_dark() {
+        let blocks = blocks();
+        // самый верхний загруженный слой лежит ниже любой поверхности рельефа
+        let mut chunks = loaded_world(&blocks, &settings(5, GeneratorKind::Terrain), (0, -3, 0));
+
+        let mut lighting = Lighting::init();
+        lighting.on_world_loaded(&blocks, &mut chunks);
+
+        let mut caves = 0;
+        for chunk in chunks.chunks.values() {
+            let (ox, oy, oz) = (chunk.x * CHUNK_W, chunk.y * CHUNK_H, chunk.z * CHUNK_D);
+            for y in oy..oy + CHUNK_H {
+                for z in oz..oz + CHUNK_D {
+                    for x in ox..ox + CHUNK_W {
+                        if chunks.get_voxel(x, y, z).unwrap().id == 0 {
+                            caves += 1;
+                            assert_eq!(chunks.get_light(x, y, z, 3), 0, "sky light in a cave at {:?}", (x, y, z));
+                        }
+                    }
+                }
+            }
+        }
+        assert!(caves > 0);
+    }
+}
@@ -1,8 +1,10 @@
 pub mod light_map;
 pub mod light_solver;
 pub mod lighting;
+pub mod sun;
 
 
 pub use light_map::LightMap;
 pub use light_solver::LightSolver;
-pub use lighting::Lighting;
\ No newline at end of file
+pub use lighting::Lighting;
+pub use sun::Sun;
\ No newline at end of file
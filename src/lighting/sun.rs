@@ -0,0 +1,82 @@
+use std::f32::consts::TAU;
+
+use glam::Vec3;
+
+
+/// Нормали граней в порядке `Block::texture_faces`: -x, +x, -y, +y, -z, +z
+const FACE_NORMALS: [Vec3; 6] = [Vec3::NEG_X, Vec3::X, Vec3::NEG_Y, Vec3::Y, Vec3::NEG_Z, Vec3::Z];
+
+/// Яркость грани, на которую солнце не светит: небо освещает её рассеянным светом
+const SHADOW_SIDE: f32 = 0.55;
+
+/// Высота солнца (y его направления), с которой оно освещает грани в полную силу.
+/// Ниже направленный свет гаснет, а ночью все грани освещены одинаково
+const FULL_SUN_HEIGHT: f32 = 0.25;
+
+
+/// Время суток и солнце. 0 - восход, 0.25 - полдень, 0.5 - закат, 0.75 - полночь
+pub struct Sun {
+    pub time: f32,
+    /// Длина суток в секундах, 0 останавливает время
+    pub day_length: f32,
+    /// Наклон плоскости, по которой идёт солнце, от вертикали к +Z, в радианах
+    pub tilt: f32,
+}
+
+
+impl Sun {
+    pub fn new(time: f32, day_length: f32, tilt: f32) -> Self {
+        Self { time: time.rem_euclid(1.0), day_length, tilt }
+    }
+
+    pub fn advance(&mut self, seconds: f32) {
+        if self.day_length > 0.0 {
+            self.time = (self.time + seconds / self.day_length).rem_euclid(1.0);
+        }
+    }
+
+    /// Единичный вектор к солнцу: восходит на +X, заходит на -X
+    pub fn direction(&self) -> Vec3 {
+        let angle = self.time * TAU;
+        Vec3::new(angle.cos(), angle.sin() * self.tilt.cos(), angle.sin() * self.tilt.sin())
+    }
+
+    /// Яркость неба 0..1 по высоте солнца: днём 1, ночью остаётся немного света
+    pub fn sky_brightness(&self) -> f32 {
+        (0.5 + self.direction().y * 3.0).clamp(0.15, 1.0)
+    }
+
+    /// Множители небесного света для граней. Свет ламп от направления не зависит
+    pub fn face_shading(&self) -> [f32; 6] {
+        let sun = self.direction();
+        let strength = (sun.y / FULL_SUN_HEIGHT).clamp(0.0, 1.0);
+        FACE_NORMALS.map(|normal| SHADOW_SIDE + (1.0 - SHADOW_SIDE) * strength * normal.dot(sun).max(0.0))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shading_follows_the_sun() {
+        let noon = Sun::new(0.25, 0.0, 0.0).face_shading();
+        assert!((noon[3] - 1.0).abs() < 1e-5);
+        assert_eq!(noon[2], SHADOW_SIDE);
+
+        let mut sun = Sun::new(0.1, 100.0, 0.3);
+        let morning = sun.face_shading();
+        sun.advance(30.0);
+        let evening = sun.face_shading();
+        // утром солнце на востоке (+X), вечером на западе (-X)
+        assert!(morning[1] > morning[0]);
+        assert!(evening[0] > evening[1]);
+        assert!((sun.time - 0.4).abs() < 1e-5);
+
+        // ночью солнце под горизонтом и не подсвечивает нижние грани
+        for time in [0.55, 0.75, 0.95] {
+            assert_eq!(Sun::new(time, 0.0, 0.3).face_shading(), [SHADOW_SIDE; 6]);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+
+use crate::files::read_whole_file;
+use crate::voxels::block::{Block, BlockStates, Blocks};
+
+
+/// Сколько тайлов помещается в атлас 16x16
+const ATLAS_TILES: i32 = 16 * 16;
+
+
+#[derive(Debug)]
+pub enum BlocksError {
+    Io { path: String, err: io::Error },
+    /// Строка файла не разобрана
+    Syntax { line: usize, reason: String },
+    DuplicateName { line: usize, name: String },
+    DuplicateId { line: usize, id: u16 },
+    DuplicateTexture { line: usize, name: String },
+    /// Блок ссылается на текстуру, которой нет в атласе
+    MissingTexture { line: usize, name: String },
+    /// У блока, начатого на строке `line`, не всем граням задана текстура
+    UntexturedFaces { line: usize, name: String },
+    /// Не задан блок 0 - воздух
+    MissingAir,
+}
+
+
+impl fmt::Display for BlocksError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlocksError::Io { path, err } => write!(f, "{}: {}", path, err),
+            BlocksError::Syntax { line, reason } => write!(f, "line {}: {}", line, reason),
+            BlocksError::DuplicateName { line, name } => {
+                write!(f, "line {}: block '{}' is already defined", line, name)
+            }
+            BlocksError::DuplicateId { line, id } => write!(f, "line {}: block id {} is already used", line, id),
+            BlocksError::DuplicateTexture { line, name } => {
+                write!(f, "line {}: texture '{}' is already defined", line, name)
+            }
+            BlocksError::MissingTexture { line, name } => {
+                write!(f, "line {}: unknown texture '{}'", line, name)
+            }
+            BlocksError::UntexturedFaces { line, name } => {
+                write!(f, "line {}: block '{}' has faces without texture", line, name)
+            }
+            BlocksError::MissingAir => write!(f, "block 0 (air) is not defined"),
+        }
+    }
+}
+
+
+impl std::error::Error for BlocksError {}
+
+
+fn read_text(path: &str) -> Result<String, BlocksError> {
+    let data = read_whole_file(path).map_err(|err| BlocksError::Io { path: path.to_string(), err })?;
+    String::from_utf8(data).map_err(|err| BlocksError::Io {
+        path: path.to_string(),
+        err: io::Error::new(io::ErrorKind::InvalidData, err),
+    })
+}
+
+
+/// Значимые строки файла с номерами: без пустых строк и комментариев после '#'
+fn lines(text: &str) -> impl Iterator<Item = (usize, Vec<&str>)> {
+    text.lines().enumerate().filter_map(|(i, line)| {
+        let line = line.split('#').next().unwrap_or("");
+        let words: Vec<&str> = line.split_whitespace().collect();
+        (!words.is_empty()).then_some((i + 1, words))
+    })
+}
+
+
+fn parse_number<T: std::str::FromStr>(line: usize, word: &str) -> Result<T, BlocksError> {
+    word.parse().map_err(|_| BlocksError::Syntax { line, reason: format!("'{}' is not a valid number", word) })
+}
+
+
+/// Имена тайлов атласа: строки вида `имя номер_тайла`
+pub fn parse_atlas(text: &str) -> Result<HashMap<String, i32>, BlocksError> {
+    let mut textures = HashMap::new();
+    for (line, words) in lines(text) {
+        let [name, tile] = words[..] else {
+            return Err(BlocksError::Syntax { line, reason: "expected '<name> <tile>'".to_string() });
+        };
+        let tile: i32 = parse_number(line, tile)?;
+        if !(0..ATLAS_TILES).contains(&tile) {
+            return Err(BlocksError::Syntax { line, reason: format!("tile {} is outside of the atlas", tile) });
+        }
+        if textures.insert(name.to_string(), tile).is_some() {
+            return Err(BlocksError::DuplicateTexture { line, name: name.to_string() });
+        }
+    }
+    Ok(textures)
+}
+
+
+/// Блок, который ещё разбирается: строка его начала и грани, получившие текстуру
+struct Pending {
+    block: Block,
+    line: usize,
+    textured: [bool; 6],
+}
+
+
+impl Pending {
+    /// Воздух не рисуется, остальным блокам нужна текстура на каждой грани
+    fn finish(self, blocks: &mut Blocks) -> Result<(), BlocksError> {
+        if self.block.id != 0 && self.textured.contains(&false) {
+            return Err(BlocksError::UntexturedFaces { line: self.line, name: self.block.name });
+        }
+        blocks.add(self.block);
+        Ok(())
+    }
+}
+
+
+/// Определения блоков. Каждый блок начинается с `block <id> <имя>`,
+/// за ним идут свойства по одному на строку:
+/// `texture`, `side`, `top`, `bottom`, `left`, `right`, `back`, `front` - текстуры граней,
+/// `emission r g b`, `draw_group n`, `light_passing`, `translucent`, `states none|axis|facing|switch`.
+/// Текстура должна быть у каждой грани, кроме граней воздуха
+pub fn parse_blocks(text: &str, textures: &HashMap<String, i32>) -> Result<Blocks, BlocksError> {
+    let mut blocks = Blocks::init();
+    let mut current: Option<Pending> = None;
+
+    for (line, words) in lines(text) {
+        let syntax = |reason: &str| BlocksError::Syntax { line, reason: reason.to_string() };
+
+        if words[0] == "block" {
+            let [_, id, name] = words[..] else {
+                return Err(syntax("expected 'block <id> <name>'"));
+            };
+            let id: u16 = parse_number(line, id)?;
+            if blocks.get(id).is_some() || current.as_ref().is_some_and(|pending| pending.block.id == id) {
+                return Err(BlocksError::DuplicateId { line, id });
+            }
+            if blocks.by_name(name).is_some() || current.as_ref().is_some_and(|pending| pending.block.name == name) {
+                return Err(BlocksError::DuplicateName { line, name: name.to_string() });
+            }
+            let pending = Pending { block: Block::new(id, name, 0), line, textured: [false; 6] };
+            if let Some(pending) = current.replace(pending) {
+                pending.finish(&mut blocks)?;
+            }
+            continue;
+        }
+
+        let Some(Pending { block, textured, .. }) = current.as_mut() else {
+            return Err(syntax("property outside of a block"));
+        };
+
+        // грани: 0 -x, 1 +x, 2 -y, 3 +y, 4 -z, 5 +z
+        let faces: &[usize] = match words[0] {
+            "texture" => &[0, 1, 2, 3, 4, 5],
+            "side" => &[0, 1, 4, 5],
+            "left" => &[0],
+            "right" => &[1],
+            "bottom" => &[2],
+            "top" => &[3],
+            "back" => &[4],
+            "front" => &[5],
+            _ => &[],
+        };
+        if !faces.is_empty() {
+            let [_, name] = words[..] else {
+                return Err(syntax("expected a texture name"));
+            };
+            let tile = *textures.get(name).ok_or_else(|| BlocksError::MissingTexture { line, name: name.to_string() })?;
+            for &face in faces {
+                block.texture_faces[face] = tile;
+                textured[face] = true;
+            }
+            continue;
+        }
+
+        match (words[0], &words[1..]) {
+            ("emission", [r, g, b]) => {
+                block.emission = [parse_number(line, r)?, parse_number(line, g)?, parse_number(line, b)?];
+                if block.emission.iter().any(|&channel| channel > 15) {
+                    return Err(syntax("emission must be in 0..=15"));
+                }
+            }
+            ("draw_group", [group]) => block.draw_group = parse_number(line, group)?,
+            ("light_passing", []) => block.light_passing = true,
+            ("translucent", []) => block.translucent = true,
+            ("states", [states]) => {
+                block.states = match *states {
+                    "none" => BlockStates::None,
+                    "axis" => BlockStates::Axis,
+                    "facing" => BlockStates::Facing,
+                    "switch" => BlockStates::Switch,
+                    other => return Err(syntax(&format!("unknown states '{}'", other))),
+                }
+            }
+            (property, _) => return Err(syntax(&format!("bad property '{}'", property))),
+        }
+    }
+
+    if let Some(pending) = current {
+        pending.finish(&mut blocks)?;
+    }
+    if blocks.get(0).is_none() {
+        return Err(BlocksError::MissingAir);
+    }
+    Ok(blocks)
+}
+
+
+/// Читает имена тайлов атласа и определения блоков
+pub fn load_blocks(blocks_path: &str, atlas_path: &str) -> Result<Blocks, BlocksError> {
+    let textures = parse_atlas(&read_text(atlas_path)?)?;
+    parse_blocks(&read_text(blocks_path)?, &textures)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn textures() -> HashMap<String, i32> {
+        parse_atlas("stone 1\ngrass_top 2\ngrass_side 3").unwrap()
+    }
+
+    #[test]
+    fn blocks_are_parsed() {
+        let text = "block 0 air\nblock 2 grass\n side grass_side\n top grass_top\n bottom stone\n\
+            block 3 glass\n texture stone\n draw_group 2\n translucent";
+        let blocks = parse_blocks(text, &textures()).unwrap();
+        assert_eq!(blocks.by_name("grass").unwrap().texture_faces, [3, 3, 1, 2, 3, 3]);
+        assert!(!blocks.by_name("grass").unwrap().translucent);
+        assert!(blocks.by_name("glass").unwrap().translucent);
+    }
+
+    #[test]
+    fn duplicates_are_rejected() {
+        let textures = textures();
+        let header = "block 0 air\nblock 1 stone\n texture stone\n";
+        assert!(matches!(
+            parse_blocks(&format!("{}block 1 rock\n texture stone", header), &textures),
+            Err(BlocksError::DuplicateId { line: 4, id: 1 })
+        ));
+        assert!(matches!(
+            parse_blocks(&format!("{}block 2 stone\n texture stone", header), &textures),
+            Err(BlocksError::DuplicateName { line: 4, .. })
+        ));
+        assert!(matches!(parse_atlas("stone 1\nstone 2"), Err(BlocksError::DuplicateTexture { line: 2, .. })));
+    }
+
+    #[test]
+    fn unknown_texture_is_rejected() {
+        assert!(matches!(
+            parse_blocks("block 0 air\nblock 1 stone\n texture marble", &textures()),
+            Err(BlocksError::MissingTexture { line: 3, name }) if name == "marble"
+        ));
+    }
+
+    #[test]
+    fn faces_without_texture_are_rejected() {
+        let textures = textures();
+        assert!(matches!(
+            parse_blocks("block 0 air\nblock 1 stone\nblock 2 grass\n texture grass_top", &textures),
+            Err(BlocksError::UntexturedFaces { line: 2, name }) if name == "stone"
+        ));
+        assert!(matches!(
+            parse_blocks("block 0 air\nblock 2 grass\n side grass_side\n top grass_top", &textures),
+            Err(BlocksError::UntexturedFaces { line: 2, .. })
+        ));
+    }
+}
@@ -1,2 +1,4 @@
 pub mod png_loading;
-pub use png_loading::load_texture;
\ No newline at end of file
+pub mod blocks_loading;
+pub use png_loading::load_texture;
+pub use blocks_loading::load_blocks;
@@ -1,14 +1,17 @@
+use std::collections::HashSet;
+use std::sync::Arc;
 use settings::*;
 use math::*;
 
 use window::{Window, Events, Camera};
-use graphics::{load_shader, VoxelRenderer, LineBatch};
-use loaders::{load_texture};
-use voxels::{Chunk, Chunks, chunk::CHUNK_D, chunk::CHUNK_W, chunk::CHUNK_H, chunk::CHUNK_VOL};
-use graphics::mesh::Mesh;
-use files::{read_binary_file, write_binary_file};
-use lighting::Lighting;
-use voxels::{Block, Blocks};
+use graphics::{load_shader, ChunkMeshes, Frustum, LineBatch, MeshingMode, Mesher};
+use graphics::voxel_renderer::lod_level;
+use loaders::{load_texture, load_blocks};
+use voxels::{Chunks, chunk::CHUNK_D, chunk::CHUNK_W, chunk::CHUNK_H};
+use graphics::mesh::{Attribute, MeshBuilder, VertexLayout};
+use graphics::occlusion::{visible_chunks, Connectivity};
+use lighting::{Lighting, Sun};
+use voxels::{Block, BlockStates};
 
 mod window;
 mod graphics;
@@ -18,6 +21,9 @@ mod settings;
 mod math;
 mod files;
 mod lighting;
+mod generation;
+#[cfg(test)]
+mod test_util;
 
 
 const VERTICES: [f32; 8] = [
@@ -32,8 +38,49 @@ const WIDTH: u32 = 1280;
 const HEIGHT: u32 = 720;
 const TITLE: &str = "Voxel_Craft";
 
-#[allow(non_upper_case_globals)]
-const attrs: [i32; 2] = [2, 0];
+/// Вершина прицела: только позиция на экране
+const CROSSHAIR_LAYOUT: VertexLayout = VertexLayout::new(&[Attribute::float(2)]);
+
+const WORLD_DIR: &str = "res/worlds/world";
+/// Мир в старом формате, переносится в регионы при первом запуске
+const LEGACY_WORLD_FILE: &str = "res/worlds/world.bin";
+
+
+fn camera_chunk(camera: &Camera) -> (isize, isize, isize) {
+    Chunks::chunk_pos(
+        camera.position.x.floor() as isize,
+        camera.position.y.floor() as isize,
+        camera.position.z.floor() as isize
+    )
+}
+
+
+/// Детализация меша чанка `key` по его удалённости от чанка камеры
+fn chunk_lod(key: (isize, isize, isize), center: (isize, isize, isize), lod_distance: f32) -> u8 {
+    let distance = (key.0 - center.0).abs().max((key.1 - center.1).abs()).max((key.2 - center.2).abs());
+    lod_level(distance, lod_distance)
+}
+
+
+/// Состояние нового блока: бревно ложится вдоль нормали грани,
+/// а блок с передней гранью поворачивается ею к игроку
+fn placement_state(block: &Block, norm: Vec3, front: Vec3) -> u8 {
+    match block.states {
+        BlockStates::Axis if norm.x != 0.0 => 1,
+        BlockStates::Axis if norm.z != 0.0 => 2,
+        BlockStates::Facing => {
+            let (dx, dz) = (-front.x, -front.z);
+            if dz.abs() >= dx.abs() {
+                if dz >= 0.0 { 0 } else { 2 }
+            } else if dx >= 0.0 {
+                1
+            } else {
+                3
+            }
+        }
+        _ => 0,
+    }
+}
 
 
 fn main() {
@@ -50,67 +97,59 @@ fn main() {
 
     let texture = load_texture("res/textures/atlas.png").expect("Failed to load texture");
 
-    let mut blocks = Blocks::init();
-
-
-    {
-        // AIR
-        let mut block = Block::new(0, 0);
-        block.draw_group = 1;
-        block.light_passing = true;
-        blocks.blocks[block.id as usize] = Some(block.clone());
-
-        // STONE
-        block = Block::new(1, 2);
-        blocks.blocks[block.id as usize] = Some(block.clone());
-
-        // GRASS
-        block = Block::new(2, 4);
-        block.texture_faces[2] = 2;
-        block.texture_faces[3] = 1;
-        blocks.blocks[block.id as usize] = Some(block.clone());
-
-        // LAMP
-        block = Block::new(3, 3);
-        block.emission[0] = 10;
-        block.emission[1] = 0;
-        block.emission[2] = 0;
-        blocks.blocks[block.id as usize] = Some(block.clone());
-
-        // GLASS
-        block = Block::new(4, 5);
-        block.draw_group = 2;
-        block.light_passing = true;
-        blocks.blocks[block.id as usize] = Some(block.clone());
-
-        // GLASS
-        block = Block::new(5, 6);
-        blocks.blocks[block.id as usize] = Some(block.clone());
-    }
+    let blocks = Arc::new(
+        load_blocks("res/blocks.txt", "res/textures/atlas.txt")
+            .unwrap_or_else(|err| panic!("Failed to load blocks: {}", err))
+    );
+
+
 
 
 
+    let mut render_settings = RenderSettings::from_args();
 
+    let mut camera = Camera::init(Vec3::new(20.0, 10.0, 20.0), render_settings.fov.to_radians());
+    camera.near = render_settings.near;
+    camera.far = render_settings.far_plane();
 
-    let mut chunks = Chunks::new(16, 16, 16);
-    let mut meshes = Vec::with_capacity(chunks.volume);
-    let mut renderer = VoxelRenderer::new(1024*1024*8);
+    let world_settings = WorldSettings::from_args();
+    let mut chunks = Chunks::new(render_settings.view_distance as isize, &world_settings, &blocks, WORLD_DIR)
+        .unwrap_or_else(|err| panic!("Failed to create world: {}", err));
+    chunks.vertical_radius = render_settings.vertical_distance as isize;
+    let time = match chunks.saved_time() {
+        Ok(time) => time.unwrap_or(world_settings.time),
+        Err(err) => {
+            eprintln!("failed to read world time: {}", err);
+            world_settings.time
+        }
+    };
+    let mut sun = Sun::new(time, render_settings.day_length, render_settings.sun_tilt);
+    let mut meshes = ChunkMeshes::new();
+    let mut mesher = Mesher::new(Arc::clone(&blocks));
 
     let mut linebatch = LineBatch::init(4096);
 
-    for i in 0..chunks.volume {
-        let mesh = renderer.render(&chunks.chunks[i], &vec![], &blocks);
-        meshes.push(mesh);
+    match chunks.migrate_legacy(LEGACY_WORLD_FILE) {
+        Ok(true) => println!("{} migrated to region files", LEGACY_WORLD_FILE),
+        Ok(false) => {}
+        Err(err) => eprintln!("failed to migrate {}: {}", LEGACY_WORLD_FILE, err),
     }
 
+    let mut center = camera_chunk(&camera);
+    // радиус, с которым чанки загружены в последний раз
+    let mut radius = chunks.radius;
+    chunks.update(center.0, center.1, center.2);
+    println!(
+        "{} chunks loaded, {} generating, voxels take {} KB",
+        chunks.chunks.len(),
+        chunks.in_flight(),
+        chunks.voxel_memory() / 1024
+    );
 
-    window.clear_color(0.0, 0.0, 0.0, 1.0);
 
     window.setting_gl();
 
-    let mut crosshair = Mesh::new(VERTICES.as_ptr(), 4, attrs.as_ptr());
-
-    let mut camera = Camera::init(Vec3::new(20.0, 10.0, 20.0), 70.0_f32.to_radians());
+    let crosshair = MeshBuilder::new(CROSSHAIR_LAYOUT).vertices(&VERTICES).build().expect("Failed to build crosshair");
 
     let mut last_time = window.glfw.get_time();
     let mut _delta:f64 = 0.0;
@@ -122,9 +161,8 @@ fn main() {
 
     let mut choosen_block = 1;
 
-    let mut buffer = vec![0u8; chunks.volume * CHUNK_VOL];
-    let _result = read_binary_file("res/worlds/world.bin", &mut buffer);
-    chunks.read(&buffer);
+    // сколько чанков нарисовано и отброшено в прошлом кадре, для заголовка окна
+    let mut culling = (0, 0, 0);
 
 
     let mut lighting = Lighting::init();
@@ -135,6 +173,7 @@ fn main() {
         let current_time = window.glfw.get_time();
         _delta = current_time - last_time;
         last_time = current_time;
+        sun.advance(_delta as f32);
 
         if events.jpressed(ESCAPE) {
             window.close();
@@ -209,10 +248,51 @@ fn main() {
         }
 
         if events.jpressed(F1) {
-            let mut buffer = vec![0u8; chunks.volume * CHUNK_VOL];
-            chunks.write(&mut buffer);
-            let _result = write_binary_file("res/worlds/world.bin", &buffer);
-            println!("world saved in {} bytes", chunks.volume * CHUNK_VOL);
+            match chunks.save(sun.time) {
+                Ok(regions) => println!("world saved, {} regions written", regions),
+                Err(err) => eprintln!("failed to save world: {}", err),
+            }
+        }
+
+        // F3 переключает способ построения мешей и перестраивает все чанки
+        if events.jpressed(F3) {
+            mesher.mode = match mesher.mode {
+                MeshingMode::PerFace => MeshingMode::Greedy,
+                MeshingMode::Greedy => MeshingMode::PerFace,
+            };
+            println!("meshing mode: {:?}", mesher.mode);
+            let keys: Vec<_> = chunks.chunks.keys().copied().collect();
+            for key in keys {
+                let lod = chunk_lod(key, center, render_settings.lod_distance);
+                mesher.submit(key, chunks.snapshot(key.0, key.1, key.2), lod);
+            }
+        }
+
+        // '-' и '=' меняют дальность прорисовки: чанки подгружаются и выгружаются при следующем обновлении
+        for (key, delta) in [(MINUS, -1.0), (EQUAL, 1.0)] {
+            if events.jpressed(key) {
+                render_settings.change_view_distance(delta);
+                chunks.radius = render_settings.view_distance as isize;
+                camera.far = render_settings.far_plane();
+                println!("view distance: {} chunks", chunks.radius);
+            }
+        }
+
+        if events.jpressed(F4) {
+            let stats = meshes.stats();
+            println!(
+                "{:?} meshing: {} triangles in {} chunk meshes ({} translucent, {} in reserve, {} created), \
+                 GPU memory: {} KB used, {} KB allocated, {} KB free",
+                mesher.mode,
+                stats.triangles,
+                stats.meshes,
+                stats.translucent,
+                stats.free_meshes,
+                stats.created,
+                stats.used_bytes / 1024,
+                stats.allocated_bytes / 1024,
+                stats.free_bytes() / 1024
+            );
         }
 
         // if events.jpressed(F2) {
@@ -239,6 +319,33 @@ fn main() {
         }
 
 
+        let current = camera_chunk(&camera);
+        let mut loaded = Vec::new();
+        if current != center || chunks.radius != radius {
+            radius = chunks.radius;
+            center = current;
+            loaded = chunks.update(center.0, center.1, center.2);
+            meshes.retain(|key| chunks.chunks.contains_key(key));
+            mesher.retain(|key| chunks.chunks.contains_key(key));
+
+            // чанки, у которых сменился уровень детализации, перестраиваются
+            let lod_distance = render_settings.lod_distance;
+            let changed: Vec<_> = chunks.chunks.keys()
+                .filter(|&&key| mesher.lod(&key).is_some_and(|lod| lod != chunk_lod(key, center, lod_distance)))
+                .copied()
+                .collect();
+            for key in changed {
+                let lod = chunk_lod(key, center, render_settings.lod_distance);
+                mesher.submit(key, chunks.snapshot(key.0, key.1, key.2), lod);
+            }
+        }
+        loaded.extend(chunks.poll());
+
+        loaded.sort_by_key(|&(_, y, _)| -y);
+        for (x, y, z) in loaded {
+            lighting.on_chunk_loaded(x, y, z, &blocks, &mut chunks);
+        }
+
         {
             let mut end = Vec3::ZERO;
             let mut norm = Vec3::ZERO;
@@ -261,9 +368,11 @@ fn main() {
                     let y = iend.y as isize;
                     let z = iend.z as isize;
 
-                    chunks.set(x, y, z, 0);
+                    if let Err(err) = chunks.set(x, y, z, 0, &blocks) {
+                        eprintln!("{}", err);
+                    }
 
-                    lighting.on_block_set(x, y, z, choosen_block, &blocks, &mut chunks);
+                    lighting.on_block_set(x, y, z, &blocks, &mut chunks);
                 }
 
                 if events.jclicked(PCM) {
@@ -271,78 +380,145 @@ fn main() {
                     let y = (iend.y + norm.y) as isize;
                     let z = (iend.z + norm.z) as isize;
 
-                    chunks.set(x, y, z, choosen_block.into());
+                    let state = match blocks.get(choosen_block) {
+                        Some(block) => placement_state(block, norm, camera.front),
+                        None => 0,
+                    };
+
+                    if let Err(err) = chunks.set_with_state(x, y, z, choosen_block.into(), state, &blocks) {
+                        eprintln!("{}", err);
+                    }
 
-                    lighting.on_block_set(x, y, z, choosen_block, &blocks, &mut chunks);
+                    lighting.on_block_set(x, y, z, &blocks, &mut chunks);
                 }
-            }
-        }
 
-        let mut closes: Vec<Option<Chunk>> = vec![None; 27];
+                // переключает состояние блока, например включает и выключает лампу
+                if events.jclicked(SCM) {
+                    let x = iend.x as isize;
+                    let y = iend.y as isize;
+                    let z = iend.z as isize;
 
-        for i in 0..chunks.volume {
-            if let Some(chunk) = chunks.chunks.get_mut(i) {
-                if !chunk.modified {
-                    continue;
+                    if let Some(block) = blocks.get(vox.id) {
+                        if block.state_count() > 1 {
+                            let state = (vox.state + 1) % block.state_count();
+                            if let Err(err) = chunks.set_with_state(x, y, z, vox.id.into(), state, &blocks) {
+                                eprintln!("{}", err);
+                            }
+                            lighting.on_block_set(x, y, z, &blocks, &mut chunks);
+                        }
+                    }
                 }
-                chunk.modified = false;
             }
-            let chunk = &chunks.chunks[i];
+        }
 
-            // if let Some(mesh) = meshes[i] {
-            //     // Освобождаем ресурсы меша
-            //     drop(mesh);
-            // }
+        let modified: Vec<_> = chunks.chunks.iter()
+            .filter(|(_, chunk)| chunk.modified)
+            .map(|(key, _)| *key)
+            .collect();
 
-            // Инициализируем массив closes снова
-            for elem in &mut closes {
-                *elem = None;
+        for key in modified {
+            if let Some(chunk) = chunks.get_mut_chunk(key.0, key.1, key.2) {
+                chunk.modified = false;
             }
 
-            for j in 0..chunks.volume {
-                let other = &chunks.chunks[j];
-                let ox = other.x - chunk.x;
-                let oy = other.y - chunk.y;
-                let oz = other.z - chunk.z;
-
-                if ox.abs() > 1 || oy.abs() > 1 || oz.abs() > 1 {
-                    continue;
-                }
+            let lod = chunk_lod(key, center, render_settings.lod_distance);
+            mesher.submit(key, chunks.snapshot(key.0, key.1, key.2), lod);
+        }
 
-                let index = ((oy + 1) * 3 + (oz + 1)) * 3 + (ox + 1);
-                closes[index as usize] = Some(other.clone());
+        for (key, geometry) in mesher.poll() {
+            if !chunks.chunks.contains_key(&key) {
+                continue;
+            }
+            if let Err(err) = meshes.upload(key, &geometry, camera.position) {
+                eprintln!("failed to upload chunk {:?}: {}", key, err);
             }
-
-            let mesh = renderer.render(chunk, &closes, &blocks);
-            meshes[i] = mesh;
         }
 
+        // небо темнеет вместе с небесным светом, туман уходит в тот же цвет
+        let sky = Vec3::from(render_settings.sky_color) * (render_settings.sky_brightness * sun.sky_brightness());
+        window.clear_color(sky.x, sky.y, sky.z, 1.0);
         window.gl_clear();
 
         shader.use_shader();
-        shader.uniform_matrix("preview", camera.get_projection(window.width() as f32, window.height() as f32) * camera.get_view());
+        let preview = camera.get_projection(window.width() as f32, window.height() as f32) * camera.get_view();
+        let frustum = Frustum::from_matrix(preview);
+        shader.uniform_matrix("preview", preview);
+        shader.uniform_float("u_ambient", render_settings.ambient);
+        shader.uniform_float("u_skyBrightness", render_settings.sky_brightness * sun.sky_brightness());
+        shader.uniform_floats("u_faceShade", &sun.face_shading());
+        shader.uniform_float("u_aoStrength", render_settings.ao_strength);
+        shader.uniform_vec3("u_cameraPos", camera.position);
+        shader.uniform_int("u_fogMode", render_settings.fog.index());
+        shader.uniform_float("u_fogStart", render_settings.fog_start * render_settings.view_blocks());
+        shader.uniform_float("u_fogEnd", render_settings.view_blocks());
+        shader.uniform_float("u_fogDensity", render_settings.fog_density);
+        shader.uniform_vec3("u_fogColor", sky);
+        shader.uniform_float("u_alphaCutoff", 0.5);
         texture.bind();
 
         let mut model = Mat4::IDENTITY;
         model *= Mat4::from_translation(vec3(0.5, 0.0, 0.0));
 
-        for i in 0..chunks.volume {
-            let chunk = &chunks.chunks[i];
-            let mesh = &meshes[i];
-            model =
-                Mat4::IDENTITY *
-                    Mat4::from_translation(
-                        vec3(
-                            ((chunk.x * CHUNK_W) as f32) + 0.5,
-                            ((chunk.y * CHUNK_H) as f32) + 0.5,
-                            ((chunk.z * CHUNK_D) as f32) + 0.5
-                        )
-                    );
+        // ещё не собранные чанки считаются прозрачными, чтобы не прятать то, что за ними
+        let visible = visible_chunks(current, |key| {
+            chunks.chunks.contains_key(key).then(|| meshes.connectivity(key).unwrap_or(Connectivity::ALL))
+        });
+
+        // сначала все непрозрачные меши, прозрачные потом рисуются поверх них от дальних к ближним
+        let mut shown = HashSet::new();
+        let (mut drawn, mut culled, mut occluded) = (0, 0, 0);
+        for (key, mesh) in meshes.iter() {
+            if !visible.contains(key) {
+                occluded += 1;
+                continue;
+            }
+            let chunk = &chunks.chunks[key];
+            // вершины чанка лежат в углах вокселей 0..=16 от угла чанка
+            let corner = vec3(
+                (chunk.x * CHUNK_W) as f32,
+                (chunk.y * CHUNK_H) as f32,
+                (chunk.z * CHUNK_D) as f32
+            );
+            if !frustum.intersects_box(corner, corner + vec3(CHUNK_W as f32, CHUNK_H as f32, CHUNK_D as f32)) {
+                culled += 1;
+                continue;
+            }
+            drawn += 1;
+            shown.insert(*key);
+
+            model = Mat4::IDENTITY * Mat4::from_translation(corner);
             shader.uniform_matrix("model", model);
             mesh.draw(TRIANGLES);
 
         }
 
+        if let Err(err) = meshes.sort_translucent(camera.position, &shown) {
+            eprintln!("failed to sort translucent faces: {}", err);
+        }
+        shader.uniform_float("u_alphaCutoff", 0.0);
+        window.depth_write(false);
+        for (key, mesh) in meshes.translucent_back_to_front(camera.position) {
+            if !shown.contains(key) {
+                continue;
+            }
+            model = Mat4::from_translation(vec3(
+                (key.0 * CHUNK_W) as f32,
+                (key.1 * CHUNK_H) as f32,
+                (key.2 * CHUNK_D) as f32
+            ));
+            shader.uniform_matrix("model", model);
+            mesh.draw(TRIANGLES);
+        }
+        window.depth_write(true);
+
+        if culling != (drawn, culled, occluded) {
+            culling = (drawn, culled, occluded);
+            window.set_title(&format!(
+                "{} - chunks: {} drawn, {} culled, {} occluded",
+                TITLE, drawn, culled, occluded
+            ));
+        }
+
 
         crosshair_shader.use_shader();
         crosshair.draw(LINES);
@@ -1,6 +1,8 @@
 use std::ffi::c_int;
 use gl::types::GLenum;
-use glfw::ffi::{KEY_1, KEY_2, KEY_3, KEY_4, KEY_5, KEY_6, KEY_7, KEY_A, KEY_D, KEY_E, KEY_ENTER, KEY_ESCAPE, KEY_F1, KEY_F2, KEY_Q, KEY_S, KEY_TAB, KEY_W, MOUSE_BUTTON_LEFT, MOUSE_BUTTON_MIDDLE, MOUSE_BUTTON_RIGHT};
+use crate::generation::{GeneratorKind, OreConfig};
+use crate::voxels::chunk::{CHUNK_H, CHUNK_W};
+use glfw::ffi::{KEY_1, KEY_2, KEY_3, KEY_4, KEY_5, KEY_6, KEY_7, KEY_A, KEY_D, KEY_E, KEY_ENTER, KEY_ESCAPE, KEY_F1, KEY_F2, KEY_F3, KEY_F4, KEY_EQUAL, KEY_MINUS, KEY_Q, KEY_S, KEY_TAB, KEY_W, MOUSE_BUTTON_LEFT, MOUSE_BUTTON_MIDDLE, MOUSE_BUTTON_RIGHT};
 
 
 /// тип рендера: треугольники
@@ -41,4 +43,223 @@ pub const K_7: c_int = KEY_7;
 /// клавиша F1
 pub const F1: c_int = KEY_F1;
 /// клавиша F2
-pub const F2: c_int = KEY_F2;
\ No newline at end of file
+pub const F2: c_int = KEY_F2;
+/// клавиша F3
+pub const F3: c_int = KEY_F3;
+/// клавиша F4
+pub const F4: c_int = KEY_F4;
+/// клавиша '-'
+pub const MINUS: c_int = KEY_MINUS;
+/// клавиша '='
+pub const EQUAL: c_int = KEY_EQUAL;
+
+
+/// Параметры создаваемого мира
+pub struct WorldSettings {
+    pub seed: u32,
+    pub generator: GeneratorKind,
+    /// Руды, которые рассыпает генератор рельефа
+    pub ores: Vec<OreConfig>,
+    /// Время суток нового мира, 0..1: 0 - восход, 0.25 - полдень. Сохранённый мир продолжает со своего
+    pub time: f32,
+}
+
+
+impl Default for WorldSettings {
+    fn default() -> Self {
+        Self {
+            seed: 1,
+            generator: GeneratorKind::Terrain,
+            ores: vec![
+                OreConfig { block: "coal_ore".to_string(), min_y: -64, max_y: 16, veins_per_chunk: 3.0, vein_size: 10 },
+                OreConfig { block: "iron_ore".to_string(), min_y: -96, max_y: -8, veins_per_chunk: 1.5, vein_size: 6 },
+            ],
+            time: 0.15,
+        }
+    }
+}
+
+
+impl WorldSettings {
+    /// Настройки из аргументов запуска: `--seed <число>`, `--generator empty|flat|noise|terrain` и `--time` в 0..1
+    pub fn from_args() -> Self {
+        let mut settings = Self::default();
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        for pair in args.windows(2) {
+            match pair[0].as_str() {
+                "--seed" => match pair[1].parse() {
+                    Ok(seed) => settings.seed = seed,
+                    Err(_) => eprintln!("bad seed '{}', using {}", pair[1], settings.seed),
+                },
+                "--generator" => match GeneratorKind::from_name(&pair[1]) {
+                    Some(generator) => settings.generator = generator,
+                    None => eprintln!("unknown generator '{}', using {:?}", pair[1], settings.generator),
+                },
+                "--time" => match pair[1].parse::<f32>() {
+                    Ok(time) => settings.time = time.clamp(0.0, 1.0),
+                    Err(_) => eprintln!("bad time '{}', using {}", pair[1], settings.time),
+                },
+                _ => {}
+            }
+        }
+        settings
+    }
+}
+
+
+/// Наибольшая дальность прорисовки в чанках
+const MAX_VIEW_DISTANCE: f32 = 16.0;
+/// Наибольшее число слоёв чанков над и под камерой
+const MAX_VERTICAL_DISTANCE: f32 = 8.0;
+
+
+/// Как туман скрывает дальние блоки
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FogMode {
+    None,
+    /// Растёт линейно от начала тумана до дальности прорисовки
+    Linear,
+    /// Растёт по экспоненте и к дальности прорисовки скрывает почти всё
+    Exponential,
+}
+
+
+impl FogMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(FogMode::None),
+            "linear" => Some(FogMode::Linear),
+            "exp" => Some(FogMode::Exponential),
+            _ => None,
+        }
+    }
+
+    /// Номер режима для шейдера
+    pub fn index(self) -> i32 {
+        self as i32
+    }
+}
+
+
+/// Параметры отрисовки, которые можно менять без перезапуска мира
+pub struct RenderSettings {
+    /// Минимальная освещённость 0..1: совсем тёмные места не становятся чёрными
+    pub ambient: f32,
+    /// Яркость небесного света 0..1, ночью меньше
+    pub sky_brightness: f32,
+    /// Сила затенения углов 0..1, 0 выключает его
+    pub ao_strength: f32,
+    /// Длина суток в секундах, 0 останавливает солнце
+    pub day_length: f32,
+    /// Наклон пути солнца от вертикали в радианах
+    pub sun_tilt: f32,
+    /// С какого расстояния в чанках меши строятся с пониженной детализацией, 0 выключает её
+    pub lod_distance: f32,
+    /// Дальность прорисовки в чанках по горизонтали: столько чанков вокруг камеры загружается и рисуется
+    pub view_distance: f32,
+    /// Сколько слоёв чанков загружается над и под камерой
+    pub vertical_distance: f32,
+    /// Угол обзора по вертикали в градусах
+    pub fov: f32,
+    /// Ближняя плоскость отсечения в блоках
+    pub near: f32,
+    /// Дальняя плоскость отсечения в блоках, 0 - по дальности прорисовки
+    pub far: f32,
+    pub fog: FogMode,
+    /// Начало линейного тумана, доля дальности прорисовки
+    pub fog_start: f32,
+    /// Плотность экспоненциального тумана: на дальности прорисовки он скрывает 1 - e^-density
+    pub fog_density: f32,
+    /// Цвет неба днём: им очищается экран и в него уходит туман
+    pub sky_color: [f32; 3],
+}
+
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            ambient: 0.1,
+            sky_brightness: 1.0,
+            ao_strength: 0.5,
+            day_length: 600.0,
+            sun_tilt: 0.4,
+            lod_distance: 3.0,
+            view_distance: 4.0,
+            vertical_distance: 3.0,
+            fov: 70.0,
+            near: 0.1,
+            far: 0.0,
+            fog: FogMode::Linear,
+            fog_start: 0.6,
+            fog_density: 3.0,
+            sky_color: [0.55, 0.7, 0.9],
+        }
+    }
+}
+
+
+impl RenderSettings {
+    /// Настройки из аргументов запуска: `--ambient`, `--sky-brightness`, `--ao` и `--fog-start` в 0..1,
+    /// `--day-length <секунды>`, `--sun-tilt <радианы>`, `--lod-distance <чанки>`, `--view-distance` и `--vertical-distance` в чанках,
+    /// `--fov <градусы>`, `--near` и `--far` в блоках, `--fog none|linear|exp` и `--fog-density`
+    pub fn from_args() -> Self {
+        let mut settings = Self::default();
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        for pair in args.windows(2) {
+            if pair[0] == "--fog" {
+                match FogMode::from_name(&pair[1]) {
+                    Some(fog) => settings.fog = fog,
+                    None => eprintln!("unknown fog '{}', using {:?}", pair[1], settings.fog),
+                }
+                continue;
+            }
+            let (value, min, max) = match pair[0].as_str() {
+                "--ambient" => (&mut settings.ambient, 0.0, 1.0),
+                "--sky-brightness" => (&mut settings.sky_brightness, 0.0, 1.0),
+                "--ao" => (&mut settings.ao_strength, 0.0, 1.0),
+                "--day-length" => (&mut settings.day_length, 0.0, f32::MAX),
+                "--sun-tilt" => (&mut settings.sun_tilt, -1.5, 1.5),
+                "--lod-distance" => (&mut settings.lod_distance, 0.0, f32::MAX),
+                "--view-distance" => (&mut settings.view_distance, 1.0, MAX_VIEW_DISTANCE),
+                "--vertical-distance" => (&mut settings.vertical_distance, 1.0, MAX_VERTICAL_DISTANCE),
+                "--fov" => (&mut settings.fov, 10.0, 150.0),
+                "--near" => (&mut settings.near, 0.01, 10.0),
+                "--far" => (&mut settings.far, 0.0, f32::MAX),
+                "--fog-start" => (&mut settings.fog_start, 0.0, 1.0),
+                "--fog-density" => (&mut settings.fog_density, 0.0, f32::MAX),
+                _ => continue,
+            };
+            match pair[1].parse::<f32>() {
+                Ok(parsed) => *value = parsed.clamp(min, max),
+                Err(_) => eprintln!("bad value '{}' for {}, using {}", pair[1], pair[0], value),
+            }
+        }
+        if settings.far != 0.0 && settings.far <= settings.near {
+            eprintln!("far plane {} is not beyond the near plane {}, using the view distance", settings.far, settings.near);
+            settings.far = 0.0;
+        }
+        settings.view_distance = settings.view_distance.round();
+        settings.vertical_distance = settings.vertical_distance.round();
+        settings
+    }
+
+    /// Дальность прорисовки в блоках
+    pub fn view_blocks(&self) -> f32 {
+        self.view_distance * CHUNK_W as f32
+    }
+
+    /// Дальняя плоскость: заданная или такая, чтобы не срезать углы самых дальних загруженных чанков
+    pub fn far_plane(&self) -> f32 {
+        if self.far > 0.0 {
+            return self.far;
+        }
+        let horizontal = (self.view_distance + 1.0) * CHUNK_W as f32;
+        let vertical = (self.vertical_distance + 1.0) * CHUNK_H as f32;
+        (horizontal * horizontal + vertical * vertical).sqrt()
+    }
+
+    /// Меняет дальность прорисовки на `delta` чанков в допустимых пределах
+    pub fn change_view_distance(&mut self, delta: f32) {
+        self.view_distance = (self.view_distance + delta).clamp(1.0, MAX_VIEW_DISTANCE);
+    }
+}
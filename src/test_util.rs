@@ -0,0 +1,32 @@
+//! Общие заготовки для тестов
+
+use crate::generation::GeneratorKind;
+use crate::loaders::load_blocks;
+use crate::settings::WorldSettings;
+use crate::voxels::{Blocks, Chunks};
+
+
+/// Блоки из res/blocks.txt
+pub fn blocks() -> Blocks {
+    load_blocks("res/blocks.txt", "res/textures/atlas.txt").unwrap()
+}
+
+
+pub fn settings(seed: u32, generator: GeneratorKind) -> WorldSettings {
+    WorldSettings { seed, generator, ..WorldSettings::default() }
+}
+
+
+/// Мир с радиусом загрузки 1 без сохранённых чанков, пока ничего не загружено
+pub fn world(blocks: &Blocks, settings: &WorldSettings) -> Chunks {
+    Chunks::new(1, settings, blocks, "target/no-world").unwrap()
+}
+
+
+/// Мир с радиусом загрузки 1, в котором загружены все чанки вокруг чанка `center`
+pub fn loaded_world(blocks: &Blocks, settings: &WorldSettings, (x, y, z): (isize, isize, isize)) -> Chunks {
+    let mut chunks = world(blocks, settings);
+    chunks.update(x, y, z);
+    chunks.wait_for(chunks.radius);
+    chunks
+}
@@ -1,40 +1,124 @@
-const BLOCK_COUNT: usize = 256;
+use std::collections::HashMap;
+use crate::voxels::voxel::{InvalidVoxel, Voxel};
+
+
+/// Какое состояние вокселя понимает блок. Состояние 0 всегда означает вид по умолчанию
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockStates {
+    None,
+    /// Ось, вдоль которой лежит блок (бревно): 0 - Y, 1 - X, 2 - Z
+    Axis,
+    /// Куда смотрит передняя грань (+Z): поворот вокруг Y на state * 90°
+    Facing,
+    /// 0 - включён, 1 - выключен. Выключенный блок не светится
+    Switch,
+}
+
+
+/// Боковые грани по кругу вокруг Y: +Z, +X, -Z, -X
+const HORIZONTAL_FACES: [usize; 4] = [5, 1, 4, 0];
 
 
 #[derive(Clone)]
 pub struct Block {
-    pub id: u32,
+    pub id: u16,
+    pub name: String,
     pub texture_faces: [i32; 6],
     pub emission: [u8; 3],
     pub draw_group: u8, // ??
     pub light_passing: bool,
+    /// Полупрозрачный блок (стекло): рисуется вторым проходом с сортировкой граней.
+    /// Блоки с дырками в текстуре (листва) рисуются в непрозрачном проходе
+    pub translucent: bool,
+    pub states: BlockStates,
 }
 
 
 pub struct Blocks {
     pub blocks: Vec<Option<Block>>,
+    names: HashMap<String, u16>,
 }
 
 
 impl Blocks {
     pub fn init() -> Self {
-        Self { blocks: vec![None; BLOCK_COUNT]  }
+        Self { blocks: Vec::new(), names: HashMap::new() }
+    }
+
+    pub fn get(&self, id: u16) -> Option<&Block> {
+        self.blocks.get(id as usize).and_then(Option::as_ref)
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&Block> {
+        self.names.get(name).and_then(|&id| self.get(id))
     }
 
-    pub fn get(&self, id: u8) -> Option<&Block> {
-        self.blocks[id as usize].as_ref()
+    pub fn add(&mut self, block: Block) {
+        let index = block.id as usize;
+        if index >= self.blocks.len() {
+            self.blocks.resize(index + 1, None);
+        }
+        self.names.insert(block.name.clone(), block.id);
+        self.blocks[index] = Some(block);
+    }
+
+    /// Проверяет, что блок вокселя определён и понимает его состояние
+    pub fn check(&self, voxel: Voxel) -> Result<Voxel, InvalidVoxel> {
+        let block = self.get(voxel.id).ok_or(InvalidVoxel::UnknownBlock(voxel.id))?;
+        let count = block.state_count();
+        if voxel.state >= count {
+            return Err(InvalidVoxel::StateOutOfRange { id: voxel.id, state: voxel.state, count });
+        }
+        Ok(voxel)
     }
 }
 
 
 impl Block {
-    pub fn new(id: u32, texture: i32) -> Self {
+    pub fn new(id: u16, name: &str, texture: i32) -> Self {
         Self {
             id,
+            name: name.to_string(),
             texture_faces: [texture; 6],
             emission: [0; 3],
             draw_group: 0,
             light_passing: false,
+            translucent: false,
+            states: BlockStates::None,
+        }
+    }
+
+    /// Количество допустимых состояний
+    pub fn state_count(&self) -> u8 {
+        match self.states {
+            BlockStates::None => 1,
+            BlockStates::Axis => 3,
+            BlockStates::Facing => 4,
+            BlockStates::Switch => 2,
         }
     }
-}
\ No newline at end of file
+
+    /// Текстуры граней с учётом поворота из состояния
+    pub fn faces(&self, state: u8) -> [i32; 6] {
+        let faces = self.texture_faces;
+        match (self.states, state) {
+            (BlockStates::Axis, 1) => [faces[2], faces[3], faces[1], faces[0], faces[4], faces[5]],
+            (BlockStates::Axis, 2) => [faces[0], faces[1], faces[4], faces[5], faces[2], faces[3]],
+            (BlockStates::Facing, state) => {
+                let mut rotated = faces;
+                for i in 0..4 {
+                    rotated[HORIZONTAL_FACES[(i + state as usize) % 4]] = faces[HORIZONTAL_FACES[i]];
+                }
+                rotated
+            }
+            _ => faces,
+        }
+    }
+
+    pub fn emission_at(&self, state: u8) -> [u8; 3] {
+        match (self.states, state) {
+            (BlockStates::Switch, 1) => [0; 3],
+            _ => self.emission,
+        }
+    }
+}
@@ -1,5 +1,6 @@
-use noise::{NoiseFn, OpenSimplex};
+use crate::files::region::{ByteReader, RegionError};
 use crate::voxels::voxel::Voxel;
+use crate::voxels::palette::{PaletteEntry, VoxelStorage};
 use crate::lighting::light_map::LightMap;
 
 
@@ -8,41 +9,103 @@ pub const CHUNK_H: isize = 16; // Y
 pub const CHUNK_D: isize = 16; // Z
 pub const CHUNK_VOL: usize = (CHUNK_W * CHUNK_H * CHUNK_D) as usize;
 
+/// Кодировки записи чанка в файле региона: id вокселей по байту,
+/// палитра с 8-битными id, с 16-битными id и с 16-битными id и состоянием
+const RECORD_RAW_U8: u8 = 0;
+const RECORD_PALETTE_U8: u8 = 1;
+const RECORD_PALETTE_U16: u8 = 2;
+const RECORD_PALETTE_STATE: u8 = 3;
+
 
 #[derive(Debug, Clone)]
 pub struct Chunk {
-    pub voxels: [Voxel; CHUNK_VOL],
+    pub voxels: VoxelStorage,
     pub x: isize,
     pub y: isize,
     pub z: isize,
     pub modified: bool,
+    /// Чанк изменён игроком и ещё не записан в регион
+    pub unsaved: bool,
     pub light_map: LightMap
 }
 
 
 impl Chunk {
-    pub fn new(x_pos: isize, y_pos: isize, z_pos: isize) -> Self {
-        let mut voxels = [Voxel {id: 0}; CHUNK_VOL];
-        let perlin = OpenSimplex::new(1);
-
-        for z in 0..CHUNK_D {
-            for x in 0..CHUNK_W {
-                let real_x = x + x_pos * CHUNK_W;
-                let real_z = z + z_pos * CHUNK_D;
-                //let height = perlin.get([(x as f64) * 0.05, (z as f64) * 0.05]);
-                for y in 0..CHUNK_H {
-                    let real_y = y + y_pos * CHUNK_H as isize;
-                    let id = perlin.get([(real_x as f64) * 0.0125, (real_y as f64) * 0.0125, (real_z as f64) * 0.0125]) > 0.1;
-                    let chunk_index = ((y * CHUNK_D + z) * CHUNK_W + x) as usize;
-                    if real_y <= 2 {
-                        voxels[chunk_index].id = 2;
-                    } else {
-                        voxels[chunk_index].id = id as u8;
-                    }
-                }
+    pub fn from_voxels(x: isize, y: isize, z: isize, voxels: &[Voxel]) -> Self {
+        let voxels = VoxelStorage::from_voxels(voxels);
+        Chunk { voxels, x, y, z, modified: true, unsaved: false, light_map: LightMap::new() }
+    }
+
+
+    pub fn index(x: usize, y: usize, z: usize) -> usize {
+        (y * CHUNK_D as usize + z) * CHUNK_W as usize + x
+    }
+
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Voxel {
+        self.voxels.get(Self::index(x, y, z))
+    }
+
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, voxel: Voxel) {
+        self.voxels.set(Self::index(x, y, z), voxel);
+    }
+
+
+    /// Запись чанка для файла региона
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.push(RECORD_PALETTE_STATE);
+        self.voxels.encode(&mut out);
+        out
+    }
+
+
+    pub fn decode(x: isize, y: isize, z: isize, record: &[u8]) -> Result<Self, RegionError> {
+        let mut reader = ByteReader::new(record);
+
+        let voxels = match reader.u8()? {
+            RECORD_RAW_U8 => {
+                let voxels: Vec<Voxel> = reader.bytes(CHUNK_VOL)?.iter().map(|&id| Voxel { id: id as u16, state: 0 }).collect();
+                VoxelStorage::from_voxels(&voxels)
             }
+            RECORD_PALETTE_U8 => VoxelStorage::decode(&mut reader, PaletteEntry::IdU8)?,
+            RECORD_PALETTE_U16 => VoxelStorage::decode(&mut reader, PaletteEntry::IdU16)?,
+            RECORD_PALETTE_STATE => VoxelStorage::decode(&mut reader, PaletteEntry::IdState)?,
+            tag => return Err(RegionError::BadRecord(format!("unknown chunk encoding {}", tag))),
+        };
+
+        Ok(Chunk { voxels, x, y, z, modified: true, unsaved: false, light_map: LightMap::new() })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_record_encoding_decodes() {
+        let mut chunk = Chunk::from_voxels(0, 0, 0, &vec![Voxel::default(); CHUNK_VOL]);
+        chunk.set(1, 2, 3, Voxel { id: 4, state: 1 });
+        let decoded = Chunk::decode(0, 0, 0, &chunk.encode()).unwrap();
+        assert_eq!(decoded.get(1, 2, 3), Voxel { id: 4, state: 1 });
+
+        let mut raw = vec![RECORD_RAW_U8];
+        raw.extend((0..CHUNK_VOL).map(|i| (i % 3) as u8));
+        let decoded = Chunk::decode(0, 0, 0, &raw).unwrap();
+        assert_eq!(decoded.voxels.get(5).id, 2);
+
+        // одна запись палитры без индексов - однородный чанк
+        for (tag, entry) in [(RECORD_PALETTE_U8, vec![6]), (RECORD_PALETTE_U16, vec![6, 0]), (RECORD_PALETTE_STATE, vec![6, 0, 1])] {
+            let mut record = vec![tag, 1, 0];
+            record.extend(entry);
+            record.push(0);
+            let decoded = Chunk::decode(0, 0, 0, &record).unwrap();
+            assert_eq!(decoded.get(15, 15, 15).id, 6);
         }
 
-        Chunk { voxels, x: x_pos, y: y_pos, z:z_pos, modified: true, light_map: LightMap::new() }
+        assert!(matches!(Chunk::decode(0, 0, 0, &[9]), Err(RegionError::BadRecord(_))));
+        assert!(matches!(Chunk::decode(0, 0, 0, &[RECORD_RAW_U8, 1]), Err(RegionError::Truncated { .. })));
     }
-}
\ No newline at end of file
+}
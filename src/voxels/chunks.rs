@@ -1,165 +1,390 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
 use glam::Vec3;
-use crate::voxels::chunk::{CHUNK_D, CHUNK_H, CHUNK_W};
-use crate::voxels::voxel::Voxel;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use crate::files::{read_whole_file, RegionError, RegionStorage};
+use crate::generation::{MissingBlock, WorldGenerator};
+use crate::settings::WorldSettings;
+use crate::voxels::chunk::{CHUNK_D, CHUNK_H, CHUNK_VOL, CHUNK_W};
+use crate::voxels::block::Blocks;
+use crate::voxels::voxel::{InvalidVoxel, Voxel};
+use crate::voxels::neighbourhood::NeighbourSnapshot;
 use super::Chunk;
 
-#[derive(Clone)]
+/// Размер старого мира из world.bin в чанках по каждой оси
+const LEGACY_SIZE: usize = 16;
+/// Чанки не дальше этого расстояния от камеры загрузка ждёт, остальные приходят по мере готовности
+const WAIT_DISTANCE: isize = 1;
+
+
+/// Результат потока генерации: воксели чанка вместе с украшениями
+struct Generated {
+    pos: (isize, isize, isize),
+    voxels: Vec<Voxel>,
+}
+
+
+/// Генерирует чанк и ставит в него свои украшения и части украшений соседних чанков.
+/// Украшения зависят только от сида, поэтому чанк собирает их сам и не ждёт соседей,
+/// а записи в чужие чанки нигде не копятся
+fn generate_chunk(generator: &dyn WorldGenerator, (x, y, z): (isize, isize, isize)) -> Generated {
+    let mut voxels = vec![Voxel::default(); CHUNK_VOL];
+    generator.generate(x, y, z, &mut voxels);
+    for oy in -1..=1 {
+        for oz in -1..=1 {
+            for ox in -1..=1 {
+                for ((wx, wy, wz), voxel) in generator.decorate(x + ox, y + oy, z + oz) {
+                    if Chunks::chunk_pos(wx, wy, wz) != (x, y, z) {
+                        continue;
+                    }
+                    let (lx, ly, lz) = Chunks::local_pos(wx, wy, wz);
+                    let index = Chunk::index(lx, ly, lz);
+                    if generator.replaces(voxels[index], voxel) {
+                        voxels[index] = voxel;
+                    }
+                }
+            }
+        }
+    }
+    Generated { pos: (x, y, z), voxels }
+}
+
+
 pub struct Chunks {
-    pub chunks: Vec<Chunk>,
-    pub volume: usize,
-    pub(crate) w: usize,
-    pub(crate) h: usize,
-    pub(crate) d: usize,
+    /// Чанки лежат в `Arc`, чтобы задания мешинга читали их без копирования.
+    /// Изменение чанка, который ещё читает задание, копирует его (`Arc::make_mut`)
+    pub chunks: HashMap<(isize, isize, isize), Arc<Chunk>>,
+    /// Радиус загрузки в чанках вокруг камеры по горизонтали
+    pub radius: isize,
+    /// Сколько слоёв чанков загружается выше и ниже камеры
+    pub vertical_radius: isize,
+    pub generator: Arc<dyn WorldGenerator>,
+    storage: RegionStorage,
+    /// Чанк, вокруг которого держится загруженная область
+    center: (isize, isize, isize),
+    /// Чанки, которые сейчас генерируются в пуле
+    in_flight: HashSet<(isize, isize, isize)>,
+    pool: ThreadPool,
+    sender: Sender<Generated>,
+    receiver: Receiver<Generated>,
 }
 
 
 impl Chunks {
-    pub fn new(w: usize, h: usize, d: usize) -> Self {
-        let volume = w * h * d;
-        let mut chunks = Vec::with_capacity(volume);
-
-        for y in 0..h {
-            for z in 0..d {
-                for x in 0..w {
-                    let chunk = Box::new(Chunk::new(x as isize, y as isize, z as isize));
-                    chunks.push(*chunk);
+    /// Генератор мира берёт id своих блоков по именам из `blocks`
+    pub fn new(radius: isize, settings: &WorldSettings, blocks: &Blocks, world_dir: &str) -> Result<Self, MissingBlock> {
+        // один поток оставляем под игровой цикл
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get().saturating_sub(1).max(1));
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("chunk-gen-{}", i))
+            .build()
+            .expect("Failed to start chunk generation threads");
+        let (sender, receiver) = channel();
+
+        Ok(Chunks {
+            chunks: HashMap::new(),
+            radius,
+            vertical_radius: radius,
+            generator: settings.generator.create(settings, blocks)?,
+            storage: RegionStorage::new(world_dir, settings.seed),
+            center: (0, 0, 0),
+            in_flight: HashSet::new(),
+            pool,
+            sender,
+            receiver,
+        })
+    }
+
+
+    /// Координаты чанка, в котором лежит воксель
+    pub fn chunk_pos(x: isize, y: isize, z: isize) -> (isize, isize, isize) {
+        (x.div_euclid(CHUNK_W), y.div_euclid(CHUNK_H), z.div_euclid(CHUNK_D))
+    }
+
+
+    /// Координаты вокселя внутри его чанка
+    pub fn local_pos(x: isize, y: isize, z: isize) -> (usize, usize, usize) {
+        (x.rem_euclid(CHUNK_W) as usize, y.rem_euclid(CHUNK_H) as usize, z.rem_euclid(CHUNK_D) as usize)
+    }
+
+
+    /// Подгружает чанки вокруг чанка (cx, cy, cz) и выгружает те, что оказались дальше, см. `in_range`.
+    /// Сохранённые чанки читаются сразу, остальные уходят в пул генерации.
+    /// Ждёт только ближайшие к камере чанки, остальные потом отдаёт `poll`.
+    /// Возвращает координаты чанков, добавленных за этот вызов.
+    pub fn update(&mut self, cx: isize, cy: isize, cz: isize) -> Vec<(isize, isize, isize)> {
+        let (radius, vertical_radius) = (self.radius, self.vertical_radius);
+        self.center = (cx, cy, cz);
+
+        let far: Vec<_> = self.chunks.keys()
+            .filter(|&&pos| !self.in_range(pos))
+            .copied()
+            .collect();
+        for key in far {
+            let chunk = self.chunks.remove(&key).unwrap();
+            if chunk.unsaved {
+                if let Err(err) = self.storage.store(key.0, key.1, key.2, chunk.encode()) {
+                    eprintln!("failed to keep chunk {:?}: {}", key, err);
                 }
             }
         }
 
-        Chunks {
-            chunks,
-            volume,
-            w,
-            h,
-            d,
+        // регионы без загруженных чанков больше не нужны в памяти
+        let regions: HashSet<_> = self.chunks.keys().map(|&(x, y, z)| RegionStorage::region_pos(x, y, z)).collect();
+        if let Err(err) = self.storage.retain(|key| regions.contains(key)) {
+            eprintln!("failed to write unused regions: {}", err);
         }
-    }
-
 
-    pub fn get_voxel<'a>(&self, x: isize, y: isize, z: isize) -> Option<&Voxel> {
-        match self.calculate_indices(x, y, z) {
-            Some((chunk_index, voxel_index, _, _, _, _, _, _)) => {
-                let chunk = self.chunks.get(chunk_index);
-                if let Some(chunk) = chunk {
-                    chunk.voxels.get(voxel_index)
-                } else {
-                    None
+        let mut missing = Vec::new();
+        for y in cy - vertical_radius..=cy + vertical_radius {
+            for z in cz - radius..=cz + radius {
+                for x in cx - radius..=cx + radius {
+                    let pos = (x, y, z);
+                    if self.in_range(pos) && !self.chunks.contains_key(&pos) && !self.in_flight.contains(&pos) {
+                        missing.push((x, y, z));
+                    }
+                }
+            }
+        }
+        missing.sort_by_key(|&(x, y, z)| (x - cx).pow(2) + (y - cy).pow(2) + (z - cz).pow(2));
+
+        let mut loaded = Vec::new();
+        for (x, y, z) in missing {
+            match self.load(x, y, z) {
+                Some(chunk) => {
+                    self.insert(chunk);
+                    loaded.push((x, y, z));
                 }
+                None => self.request(x, y, z),
             }
-            None => None,
         }
+
+        loaded.extend(self.wait_for(WAIT_DISTANCE));
+        loaded
     }
 
 
-    pub fn get_mut_chunk_by_voxel(&mut self, x: isize, y: isize, z: isize) -> Option<&mut Chunk> {
-        match self.calculate_indices(x, y, z) {
-            Some((chunk_index, _, _, _, _, _, _, _)) => self.chunks.get_mut(chunk_index),
-            None => None,
+    /// Забирает готовые чанки из пула, не блокируясь
+    pub fn poll(&mut self) -> Vec<(isize, isize, isize)> {
+        let mut loaded = Vec::new();
+        while let Ok(generated) = self.receiver.try_recv() {
+            loaded.extend(self.accept(generated));
         }
+        loaded
     }
 
 
-    pub fn get(&self, x: isize, y: isize, z: isize) -> Option<&Voxel> {
-        let mut cx = x / CHUNK_W;
-        let mut cy = y / CHUNK_H;
-        let mut cz = z / CHUNK_D;
-
-        if x < 0 {
-            cx -= 1;
-        }
-        if y < 0 {
-            cy -= 1;
-        }
-        if z < 0 {
-            cz -= 1;
+    /// Ждёт, пока догенерируются все запрошенные чанки не дальше `distance` от центра
+    pub fn wait_for(&mut self, distance: isize) -> Vec<(isize, isize, isize)> {
+        let mut loaded = self.poll();
+        while self.in_flight.iter().any(|&pos| self.distance(pos) <= distance) {
+            match self.receiver.recv() {
+                Ok(generated) => loaded.extend(self.accept(generated)),
+                Err(_) => break,
+            }
         }
+        loaded
+    }
 
-        if
-        cx < 0 ||
-            cy < 0 ||
-            cz < 0 ||
-            cx >= (self.w as isize) ||
-            cy >= (self.h as isize) ||
-            cz >= (self.d as isize)
-        {
-            return None;
-        }
 
-        let chunk_index = ((cy * (self.d as isize) + cz) * (self.w as isize) + cx) as usize;
-        let chunk = &self.chunks[chunk_index];
+    /// Сколько чанков ещё генерируется
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
 
-        let lx = x - cx * (CHUNK_W);
-        let ly = y - cy * (CHUNK_H);
-        let lz = z - cz * (CHUNK_D);
 
-        let voxel_index = ((ly * (CHUNK_D) + lz) * (CHUNK_W) + lx) as usize;
-        Some(&chunk.voxels[voxel_index])
+    /// Расстояние от центра загрузки в чанках по самой дальней оси
+    fn distance(&self, (x, y, z): (isize, isize, isize)) -> isize {
+        let (cx, cy, cz) = self.center;
+        (x - cx).abs().max((y - cy).abs()).max((z - cz).abs())
     }
 
 
-    pub fn get_chunk(&self, x: isize, y: isize, z: isize) -> Option<&Chunk> {
-        if
-        x < 0 ||
-            y < 0 ||
-            z < 0 ||
-            x >= (self.w as isize) ||
-            y >= (self.h as isize) ||
-            z >= (self.d as isize)
-        {
+    /// Нужен ли чанк при текущем центре: по горизонтали он в круге радиуса `radius`
+    /// (с запасом в полчанка, чтобы углы квадрата 3x3 попадали в радиус 1),
+    /// по вертикали не дальше `vertical_radius` слоёв
+    pub fn in_range(&self, (x, y, z): (isize, isize, isize)) -> bool {
+        let (cx, cy, cz) = self.center;
+        let (dx, dz) = (x - cx, z - cz);
+        dx * dx + dz * dz <= self.radius * (self.radius + 1) && (y - cy).abs() <= self.vertical_radius
+    }
+
+
+    fn request(&mut self, x: isize, y: isize, z: isize) {
+        self.in_flight.insert((x, y, z));
+        let generator = Arc::clone(&self.generator);
+        let sender = self.sender.clone();
+        self.pool.spawn(move || {
+            // приёмник живёт вместе с Chunks, ошибка значит, что мир уже закрыт
+            let _ = sender.send(generate_chunk(&*generator, (x, y, z)));
+        });
+    }
+
+
+    /// Добавляет сгенерированный чанк, если он ещё нужен
+    fn accept(&mut self, generated: Generated) -> Option<(isize, isize, isize)> {
+        let Generated { pos, voxels } = generated;
+        self.in_flight.remove(&pos);
+        if !self.in_range(pos) || self.chunks.contains_key(&pos) {
             return None;
         }
-        let chunk_index = (y * (self.d as isize) + z) * (self.w as isize) + x;
-        Some(&self.chunks[chunk_index as usize])
+        self.insert(Chunk::from_voxels(pos.0, pos.1, pos.2, &voxels));
+        Some(pos)
     }
 
 
-    pub fn get_mut_chunk(&mut self, x: isize, y: isize, z: isize) -> Option<&mut Chunk> {
-        if
-        x < 0 ||
-            y < 0 ||
-            z < 0 ||
-            x >= (self.w as isize) ||
-            y >= (self.h as isize) ||
-            z >= (self.d as isize)
-        {
-            return None;
+    /// Кладёт чанк в мир и помечает соседей для перестройки
+    fn insert(&mut self, chunk: Chunk) {
+        let (x, y, z) = (chunk.x, chunk.y, chunk.z);
+        self.chunks.insert((x, y, z), Arc::new(chunk));
+
+        for (ox, oy, oz) in [(-1, 0, 0), (1, 0, 0), (0, -1, 0), (0, 1, 0), (0, 0, -1), (0, 0, 1)] {
+            if let Some(chunk) = self.get_mut_chunk(x + ox, y + oy, z + oz) {
+                chunk.modified = true;
+            }
+        }
+    }
+
+
+    /// Сохранённый чанк из файла региона. Если его нет или запись битая, чанк надо генерировать
+    fn load(&mut self, x: isize, y: isize, z: isize) -> Option<Chunk> {
+        match self.storage.load(x, y, z) {
+            Ok(Some(record)) => match Chunk::decode(x, y, z, record) {
+                Ok(chunk) => return Some(chunk),
+                Err(err) => eprintln!("failed to read chunk {:?}: {}", (x, y, z), err),
+            },
+            Ok(None) => {}
+            Err(err) => eprintln!("failed to read region of chunk {:?}: {}", (x, y, z), err),
         }
-        let chunk_index = (y * (self.d as isize) + z) * (self.w as isize) + x;
-        Some(&mut self.chunks[chunk_index as usize])
+        None
     }
 
-    pub fn set(&mut self, x: isize, y: isize, z: isize, id: i32) {
-        let mut cx = x / (CHUNK_W as isize);
-        let mut cy = y / (CHUNK_H as isize);
-        let mut cz = z / (CHUNK_D as isize);
-        if x < 0 {
-            cx -= 1;
+
+    /// Записывает изменённые чанки в файлы регионов и время суток `time` в папку мира
+    pub fn save(&mut self, time: f32) -> Result<usize, RegionError> {
+        self.storage.save_time(time)?;
+        for (&(x, y, z), chunk) in self.chunks.iter_mut() {
+            if chunk.unsaved {
+                self.storage.store(x, y, z, chunk.encode())?;
+                Arc::make_mut(chunk).unsaved = false;
+            }
         }
-        if y < 0 {
-            cy -= 1;
+        self.storage.flush()
+    }
+
+
+    /// Время суток из последнего сохранения мира
+    pub fn saved_time(&self) -> Result<Option<f32>, RegionError> {
+        self.storage.load_time()
+    }
+
+
+    /// Сколько байт занимают воксели загруженных чанков
+    pub fn voxel_memory(&self) -> usize {
+        self.chunks.values().map(|chunk| chunk.voxels.memory_usage()).sum()
+    }
+
+
+    /// Переносит мир из старого world.bin (сетка 16x16x16 чанков, id по байту) в регионы.
+    /// Возвращает false, если такого файла нет.
+    pub fn migrate_legacy(&mut self, path: &str) -> Result<bool, RegionError> {
+        self.migrate_grid(path, LEGACY_SIZE)
+    }
+
+
+    /// Перенос старого мира из сетки `size`³ чанков
+    fn migrate_grid(&mut self, path: &str, size: usize) -> Result<bool, RegionError> {
+        let data = match read_whole_file(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        let expected = size * size * size * CHUNK_VOL;
+        if data.len() < expected {
+            return Err(RegionError::Truncated { needed: expected, len: data.len() });
         }
-        if z < 0 {
-            cz -= 1;
+        if data.len() > expected {
+            return Err(RegionError::SizeMismatch { expected, len: data.len() });
         }
-        if
-        cx < 0 ||
-            cy < 0 ||
-            cz < 0 ||
-            cx >= (self.w as isize) ||
-            cy >= (self.h as isize) ||
-            cz >= (self.d as isize)
-        {
-            return;
+
+        for (index, ids) in data.chunks_exact(CHUNK_VOL).enumerate() {
+            let x = (index % size) as isize;
+            let z = (index / size % size) as isize;
+            let y = (index / (size * size)) as isize;
+            let voxels: Vec<Voxel> = ids.iter().map(|&id| Voxel { id: id as u16, state: 0 }).collect();
+            self.storage.store(x, y, z, Chunk::from_voxels(x, y, z, &voxels).encode())?;
+            self.chunks.remove(&(x, y, z));
         }
-        let chunk_index = ((cy * (self.d as isize) + cz) * (self.w as isize) + cx) as usize;
-        let chunk = &mut self.chunks[chunk_index];
-        let lx = x - cx * (CHUNK_W as isize);
-        let ly = y - cy * (CHUNK_H as isize);
-        let lz = z - cz * (CHUNK_D as isize);
-        println!("id: {}, id: {}", id, id as u8);
-        chunk.voxels[((ly * (CHUNK_D as isize) + lz) * (CHUNK_W as isize) + lx) as usize].id = id as u8;
+        self.storage.flush()?;
+        std::fs::rename(path, format!("{}.migrated", path))?;
+        Ok(true)
+    }
+
+
+    pub fn get_voxel(&self, x: isize, y: isize, z: isize) -> Option<Voxel> {
+        self.get(x, y, z)
+    }
+
+
+    pub fn get_mut_chunk_by_voxel(&mut self, x: isize, y: isize, z: isize) -> Option<&mut Chunk> {
+        let (cx, cy, cz) = Self::chunk_pos(x, y, z);
+        self.get_mut_chunk(cx, cy, cz)
+    }
+
+
+    /// Воксель по мировым координатам. `None`, если чанк не загружен
+    pub fn get(&self, x: isize, y: isize, z: isize) -> Option<Voxel> {
+        let ((cx, cy, cz), voxel_index) = Self::calculate_indices(x, y, z);
+        self.get_chunk(cx, cy, cz).map(|chunk| chunk.voxels.get(voxel_index))
+    }
+
+
+    pub fn get_chunk(&self, x: isize, y: isize, z: isize) -> Option<&Chunk> {
+        self.chunks.get(&(x, y, z)).map(|chunk| &**chunk)
+    }
+
+
+    /// Чанк (cx, cy, cz) и его соседи для мешинга в другом потоке
+    pub fn snapshot(&self, cx: isize, cy: isize, cz: isize) -> NeighbourSnapshot {
+        std::array::from_fn(|i| {
+            let i = i as isize;
+            self.chunks.get(&(cx + i % 3 - 1, cy + i / 9 - 1, cz + i / 3 % 3 - 1)).cloned()
+        })
+    }
+
+
+    pub fn get_mut_chunk(&mut self, x: isize, y: isize, z: isize) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&(x, y, z)).map(Arc::make_mut)
+    }
+
+    /// Ставит блок. В незагруженный чанк ничего не пишется
+    pub fn set(&mut self, x: isize, y: isize, z: isize, id: i32, blocks: &Blocks) -> Result<(), InvalidVoxel> {
+        self.set_with_state(x, y, z, id, 0, blocks)
+    }
+
+
+    /// Ставит блок с состоянием, если `blocks` знает такой блок и состояние
+    pub fn set_with_state(&mut self, x: isize, y: isize, z: isize, id: i32, state: u8, blocks: &Blocks) -> Result<(), InvalidVoxel> {
+        let voxel = blocks.check(Voxel::with_state(id, state)?)?;
+        self.set_voxel(x, y, z, voxel);
+        Ok(())
+    }
+
+
+    /// Ставит воксель в загруженный чанк и помечает чанки, которые надо перестроить
+    pub fn set_voxel(&mut self, x: isize, y: isize, z: isize, voxel: Voxel) {
+        let (cx, cy, cz) = Self::chunk_pos(x, y, z);
+        let (lx, ly, lz) = Self::local_pos(x, y, z);
+
+        let chunk = match self.get_mut_chunk(cx, cy, cz) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+        chunk.set(lx, ly, lz, voxel);
         chunk.modified = true;
+        chunk.unsaved = true;
 
         if lx == 0 {
             if let Some(chunk) = self.get_mut_chunk(cx - 1, cy, cz) {
@@ -177,17 +402,17 @@ impl Chunks {
             }
         }
 
-        if lx == (CHUNK_W as isize) - 1 {
+        if lx == (CHUNK_W as usize) - 1 {
             if let Some(chunk) = self.get_mut_chunk(cx + 1, cy, cz) {
                 chunk.modified = true;
             }
         }
-        if ly == (CHUNK_H as isize) - 1 {
+        if ly == (CHUNK_H as usize) - 1 {
             if let Some(chunk) = self.get_mut_chunk(cx, cy + 1, cz) {
                 chunk.modified = true;
             }
         }
-        if lz == (CHUNK_D as isize) - 1 {
+        if lz == (CHUNK_D as usize) - 1 {
             if let Some(chunk) = self.get_mut_chunk(cx, cy, cz + 1) {
                 chunk.modified = true;
             }
@@ -203,10 +428,10 @@ impl Chunks {
         end: &mut Vec3,
         norm: &mut Vec3,
         iend: &mut Vec3,
-    ) -> Option<&Voxel> {
-        let mut px = a.x;
-        let mut py = a.y;
-        let mut pz = a.z;
+    ) -> Option<Voxel> {
+        let px = a.x;
+        let py = a.y;
+        let pz = a.z;
 
         let dx = dir.x;
         let dy = dir.y;
@@ -302,34 +527,13 @@ impl Chunks {
     }
 
 
-    pub fn write(&self, dest: &mut [u8]) {
-        let mut index = 0;
-        for chunk in &self.chunks {
-            for voxel in &chunk.voxels {
-                dest[index] = voxel.id;
-                index += 1;
-            }
-        }
-    }
-
-
-    pub fn read(&mut self, source: &[u8]) {
-        let mut index = 0;
-        for chunk in &mut self.chunks {
-            for voxel in chunk.voxels.iter_mut() {
-                voxel.id = source[index];
-                index += 1;
-            }
-            chunk.modified = true;
-        }
-    }
-
-
+    /// Свет в мировых координатах. Для незагруженного чанка 0
     pub fn get_light(&self, x: isize, y: isize, z: isize, channel: usize) -> u8 {
-        match self.calculate_indices(x, y, z) {
-            Some((chunk_index, _, _, _, _, lx, ly, lz)) => {
-                let chunk = &self.chunks[chunk_index];
-                chunk.light_map.get(lx as usize, ly as usize, lz as usize, channel)
+        let (cx, cy, cz) = Self::chunk_pos(x, y, z);
+        match self.get_chunk(cx, cy, cz) {
+            Some(chunk) => {
+                let (lx, ly, lz) = Self::local_pos(x, y, z);
+                chunk.light_map.get(lx, ly, lz, channel)
             }
             None => 0,
         }
@@ -337,38 +541,118 @@ impl Chunks {
 
 
     fn calculate_indices(
-        &self,
         x: isize,
         y: isize,
         z: isize
-    ) -> Option<(usize, usize, isize, isize, isize, isize, isize, isize)> {
-        let mut cx = x / (CHUNK_W as isize);
-        let mut cy = y / (CHUNK_H as isize);
-        let mut cz = z / (CHUNK_D as isize);
-        if x < 0 {
-            cx -= 1;
+    ) -> ((isize, isize, isize), usize) {
+        let (lx, ly, lz) = Self::local_pos(x, y, z);
+        let voxel_index = (ly * (CHUNK_D as usize) + lz) * (CHUNK_W as usize) + lx;
+        (Self::chunk_pos(x, y, z), voxel_index)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generation::GeneratorKind;
+    use crate::test_util::{blocks, settings, world};
+
+    #[test]
+    fn decorations_do_not_depend_on_generation_order() {
+        let mut order = Vec::new();
+        for y in -1..=1 {
+            for z in -1..=1 {
+                for x in -1..=1 {
+                    order.push((x, y, z));
+                }
+            }
         }
-        if y < 0 {
-            cy -= 1;
+
+        // второй мир получает те же чанки в обратном порядке, как если бы потоки закончили иначе
+        let blocks = blocks();
+        let (log, leaves) = (blocks.by_name("log").unwrap().id, blocks.by_name("leaves").unwrap().id);
+        let settings = settings(11, GeneratorKind::Terrain);
+        let mut first = world(&blocks, &settings);
+        let mut second = world(&blocks, &settings);
+        for (chunks, order) in [(&mut first, order.clone()), (&mut second, order.into_iter().rev().collect())] {
+            for pos in order {
+                let generated = generate_chunk(&*chunks.generator, pos);
+                chunks.accept(generated);
+            }
         }
-        if z < 0 {
-            cz -= 1;
+
+        let mut decorated = 0;
+        for (key, chunk) in &first.chunks {
+            let voxels: Vec<Voxel> = chunk.voxels.iter().collect();
+            assert!(voxels == second.chunks[key].voxels.iter().collect::<Vec<_>>(), "chunk {:?} differs", key);
+            decorated += voxels.iter().filter(|voxel| voxel.id == log || voxel.id == leaves).count();
         }
-        if
-        cx < 0 ||
-            cy < 0 ||
-            cz < 0 ||
-            cx >= (self.w as isize) ||
-            cy >= (self.h as isize) ||
-            cz >= (self.d as isize)
-        {
-            return None;
+        assert!(decorated > 0);
+    }
+
+    #[test]
+    fn legacy_world_is_migrated_to_regions() {
+        let dir = "target/test-legacy";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let path = format!("{}/world.bin", dir);
+        let settings = settings(1, GeneratorKind::Empty);
+
+        // сетка 2x2x2 чанков, в каждом чанке id равен его номеру в файле
+        let data: Vec<u8> = (0..8u8).flat_map(|index| std::iter::repeat_n(index + 1, CHUNK_VOL)).collect();
+        std::fs::write(&path, [&data[..], &[0]].concat()).unwrap();
+        let blocks = blocks();
+        let mut chunks = Chunks::new(1, &settings, &blocks, &format!("{}/world", dir)).unwrap();
+        assert!(matches!(
+            chunks.migrate_grid(&path, 2),
+            Err(RegionError::SizeMismatch { expected, len }) if expected == data.len() && len == data.len() + 1
+        ));
+        std::fs::write(&path, &data[1..]).unwrap();
+        assert!(matches!(chunks.migrate_grid(&path, 2), Err(RegionError::Truncated { .. })));
+
+        std::fs::write(&path, &data).unwrap();
+        assert!(chunks.migrate_grid(&path, 2).unwrap());
+        assert!(!std::path::Path::new(&path).exists());
+        assert!(!chunks.migrate_grid(&path, 2).unwrap());
+
+        let mut chunks = Chunks::new(1, &settings, &blocks, &format!("{}/world", dir)).unwrap();
+        // x + z * 2 + y * 4
+        for (pos, id) in [((0, 0, 0), 1), ((1, 0, 0), 2), ((0, 0, 1), 3), ((1, 1, 1), 8)] {
+            let chunk = chunks.load(pos.0, pos.1, pos.2).unwrap();
+            assert!(chunk.voxels.iter().all(|voxel| voxel == Voxel { id, state: 0 }));
         }
-        let chunk_index = ((cy * (self.d as isize) + cz) * (self.w as isize) + cx) as usize;
-        let lx = x - cx * (CHUNK_W as isize);
-        let ly = y - cy * (CHUNK_H as isize);
-        let lz = z - cz * (CHUNK_D as isize);
-        let voxel_index = ((ly * (CHUNK_D as isize) + lz) * (CHUNK_W as isize) + lx) as usize;
-        Some((chunk_index, voxel_index, cx, cy, cz, lx, ly, lz))
-    }
-}
\ No newline at end of file
+    }
+
+    #[test]
+    fn invalid_voxels_are_not_set() {
+        let blocks = blocks();
+        let mut chunks = world(&blocks, &settings(1, GeneratorKind::Empty));
+        chunks.chunks.insert((0, 0, 0), Arc::new(Chunk::from_voxels(0, 0, 0, &[Voxel::default(); CHUNK_VOL])));
+        let log = blocks.by_name("log").unwrap().id;
+
+        assert!(chunks.set_with_state(1, 2, 3, log.into(), 2, &blocks).is_ok());
+        assert_eq!(
+            chunks.set_with_state(1, 2, 3, log.into(), 3, &blocks),
+            Err(InvalidVoxel::StateOutOfRange { id: log, state: 3, count: 3 })
+        );
+        assert_eq!(chunks.set(1, 2, 3, 4000, &blocks), Err(InvalidVoxel::UnknownBlock(4000)));
+        assert_eq!(chunks.set(1, 2, 3, -1, &blocks), Err(InvalidVoxel::IdOutOfRange(-1)));
+        assert_eq!(chunks.get(1, 2, 3), Some(Voxel { id: log, state: 2 }));
+    }
+
+    #[test]
+    fn loaded_area_is_flat() {
+        let blocks = blocks();
+        let mut chunks = Chunks::new(3, &settings(1, GeneratorKind::Empty), &blocks, "target/no-world").unwrap();
+        chunks.vertical_radius = 1;
+        chunks.update(0, 5, 0);
+        chunks.wait_for(chunks.radius);
+
+        assert!(chunks.chunks.keys().all(|&(_, y, _)| (4..=6).contains(&y)));
+        assert!(chunks.chunks.contains_key(&(3, 5, 0)) && chunks.chunks.contains_key(&(2, 6, 2)));
+        // углы квадрата 7x7 дальше радиуса по горизонтали
+        assert!(!chunks.chunks.contains_key(&(3, 5, 3)));
+        assert_eq!(chunks.chunks.len(), 3 * 37);
+    }
+}
@@ -2,8 +2,11 @@ pub mod voxel;
 pub mod chunk;
 pub mod chunks;
 pub mod block;
+pub mod palette;
+pub mod neighbourhood;
 
 pub use chunk::Chunk;
 pub use chunks::Chunks;
 pub use block::Block;
-pub use block::Blocks;
\ No newline at end of file
+pub use block::Blocks;
+pub use block::BlockStates;
\ No newline at end of file
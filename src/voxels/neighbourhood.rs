@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use crate::voxels::chunk::{Chunk, CHUNK_D, CHUNK_H, CHUNK_W};
+use crate::voxels::voxel::Voxel;
+
+
+/// Снимок чанка и его 26 соседей: дешёвые копии `Arc`, которые можно отдать в другой поток
+pub type NeighbourSnapshot = [Option<Arc<Chunk>>; 27];
+
+
+/// Чанк и его 26 соседей по ссылке, без копирования.
+/// Координаты отсчитываются от угла центрального чанка, соседи лежат в -16..32
+pub struct Neighbourhood<'a> {
+    chunks: [Option<&'a Chunk>; 27],
+}
+
+
+impl<'a> Neighbourhood<'a> {
+    /// `chunks[((oy + 1) * 3 + oz + 1) * 3 + ox + 1]` - сосед со смещением (ox, oy, oz)
+    pub fn new(chunks: [Option<&'a Chunk>; 27]) -> Self {
+        Self { chunks }
+    }
+
+    pub fn from_snapshot(snapshot: &'a NeighbourSnapshot) -> Self {
+        Self::new(std::array::from_fn(|i| snapshot[i].as_deref()))
+    }
+
+    pub fn index(ox: isize, oy: isize, oz: isize) -> usize {
+        (((oy + 1) * 3 + oz + 1) * 3 + ox + 1) as usize
+    }
+
+    pub fn center(&self) -> &'a Chunk {
+        self.chunks[13].expect("neighbourhood without the center chunk")
+    }
+
+    pub fn chunk(&self, x: isize, y: isize, z: isize) -> Option<&'a Chunk> {
+        let (ox, oy, oz) = (x.div_euclid(CHUNK_W), y.div_euclid(CHUNK_H), z.div_euclid(CHUNK_D));
+        if ox.abs() > 1 || oy.abs() > 1 || oz.abs() > 1 {
+            return None;
+        }
+        self.chunks[Self::index(ox, oy, oz)]
+    }
+
+    pub fn voxel(&self, x: isize, y: isize, z: isize) -> Option<Voxel> {
+        let chunk = self.chunk(x, y, z)?;
+        Some(chunk.get(
+            x.rem_euclid(CHUNK_W) as usize,
+            y.rem_euclid(CHUNK_H) as usize,
+            z.rem_euclid(CHUNK_D) as usize,
+        ))
+    }
+
+    /// Свет канала `channel`, 0 за пределами загруженных чанков
+    pub fn light(&self, x: isize, y: isize, z: isize, channel: usize) -> u8 {
+        match self.chunk(x, y, z) {
+            Some(chunk) => chunk.light_map.get(
+                x.rem_euclid(CHUNK_W) as usize,
+                y.rem_euclid(CHUNK_H) as usize,
+                z.rem_euclid(CHUNK_D) as usize,
+                channel,
+            ),
+            None => 0,
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use crate::files::region::{ByteReader, RegionError};
+use crate::voxels::chunk::CHUNK_VOL;
+use crate::voxels::voxel::Voxel;
+
+
+/// Как записана одна запись палитры в файле
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteEntry {
+    IdU8,
+    IdU16,
+    IdState,
+}
+
+
+/// Хранилище вокселей чанка: одно значение на весь чанк
+/// или палитра с упакованными в u64 индексами
+#[derive(Debug, Clone)]
+pub enum VoxelStorage {
+    Uniform(Voxel),
+    Palette {
+        palette: Vec<Voxel>,
+        /// Сколько вокселей ссылается на каждую запись палитры. Запись с нулём занимает следующий новый воксель
+        counts: Vec<u16>,
+        bits: u32,
+        data: Vec<u64>,
+    },
+}
+
+
+fn bits_for(len: usize) -> u32 {
+    (usize::BITS - (len.max(2) - 1).leading_zeros()).max(1)
+}
+
+
+fn words_for(bits: u32) -> usize {
+    let per_word = (64 / bits) as usize;
+    CHUNK_VOL.div_ceil(per_word)
+}
+
+
+fn read_packed(data: &[u64], bits: u32, index: usize) -> usize {
+    let per_word = (64 / bits) as usize;
+    let shift = (index % per_word) as u32 * bits;
+    ((data[index / per_word] >> shift) & ((1u64 << bits) - 1)) as usize
+}
+
+
+fn write_packed(data: &mut [u64], bits: u32, index: usize, value: usize) {
+    let per_word = (64 / bits) as usize;
+    let shift = (index % per_word) as u32 * bits;
+    let mask = ((1u64 << bits) - 1) << shift;
+    let word = &mut data[index / per_word];
+    *word = (*word & !mask) | ((value as u64) << shift);
+}
+
+
+impl VoxelStorage {
+    /// Строит хранилище с минимальной палитрой по готовому массиву вокселей
+    pub fn from_voxels(voxels: &[Voxel]) -> Self {
+        let mut palette: Vec<Voxel> = Vec::new();
+        let mut indices = Vec::with_capacity(voxels.len());
+        for voxel in voxels {
+            let index = match palette.iter().position(|v| v == voxel) {
+                Some(index) => index,
+                None => {
+                    palette.push(*voxel);
+                    palette.len() - 1
+                }
+            };
+            indices.push(index);
+        }
+
+        if palette.len() <= 1 {
+            return VoxelStorage::Uniform(palette.first().copied().unwrap_or_default());
+        }
+
+        let bits = bits_for(palette.len());
+        let mut data = vec![0; words_for(bits)];
+        let mut counts = vec![0; palette.len()];
+        for (i, index) in indices.into_iter().enumerate() {
+            write_packed(&mut data, bits, i, index);
+            counts[index] += 1;
+        }
+        VoxelStorage::Palette { palette, counts, bits, data }
+    }
+
+    pub fn get(&self, index: usize) -> Voxel {
+        match self {
+            VoxelStorage::Uniform(voxel) => *voxel,
+            VoxelStorage::Palette { palette, bits, data, .. } => palette[read_packed(data, *bits, index)],
+        }
+    }
+
+    /// Ставит воксель. Освободившиеся записи палитры переиспользуются, а когда записей в ходу
+    /// становится мало для текущей ширины индексов, палитра сжимается, так что ширина
+    /// следует за числом разных вокселей в чанке, а не за историей правок
+    pub fn set(&mut self, index: usize, voxel: Voxel) {
+        if let VoxelStorage::Uniform(current) = *self {
+            if current == voxel {
+                return;
+            }
+            *self = VoxelStorage::Palette {
+                palette: vec![current],
+                counts: vec![CHUNK_VOL as u16],
+                bits: 1,
+                data: vec![0; words_for(1)],
+            };
+        }
+
+        let VoxelStorage::Palette { palette, counts, bits, data } = self else {
+            return;
+        };
+        let old = read_packed(data, *bits, index);
+        if palette[old] == voxel {
+            return;
+        }
+        let palette_index = match palette.iter().position(|v| *v == voxel) {
+            Some(palette_index) => palette_index,
+            None => match counts.iter().position(|&count| count == 0) {
+                Some(free) => {
+                    palette[free] = voxel;
+                    free
+                }
+                None => {
+                    palette.push(voxel);
+                    counts.push(0);
+                    let needed = bits_for(palette.len());
+                    if needed > *bits {
+                        let mut repacked = vec![0; words_for(needed)];
+                        for i in 0..CHUNK_VOL {
+                            write_packed(&mut repacked, needed, i, read_packed(data, *bits, i));
+                        }
+                        *data = repacked;
+                        *bits = needed;
+                    }
+                    palette.len() - 1
+                }
+            },
+        };
+        write_packed(data, *bits, index, palette_index);
+        counts[old] -= 1;
+        counts[palette_index] += 1;
+
+        let in_use = counts.iter().filter(|&&count| count > 0).count();
+        if in_use <= 1 || bits_for(in_use) < *bits {
+            self.compact();
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Voxel> + '_ {
+        (0..CHUNK_VOL).map(move |i| self.get(i))
+    }
+
+    /// Перестраивает палитру, выкидывая записи, которые больше не используются
+    pub fn compact(&mut self) {
+        if let VoxelStorage::Palette { .. } = self {
+            let voxels: Vec<Voxel> = self.iter().collect();
+            *self = VoxelStorage::from_voxels(&voxels);
+        }
+    }
+
+    /// Примерный объём памяти под воксели в байтах
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            VoxelStorage::Uniform(_) => size_of::<Voxel>(),
+            VoxelStorage::Palette { palette, counts, data, .. } => {
+                palette.len() * size_of::<Voxel>() + counts.len() * size_of::<u16>() + data.len() * size_of::<u64>()
+            }
+        }
+    }
+
+    /// [размер палитры u16][id u16 и состояние u8 на запись палитры][биты u8][слова u64]
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let mut compacted = self.clone();
+        compacted.compact();
+        match &compacted {
+            VoxelStorage::Uniform(voxel) => {
+                out.extend_from_slice(&1u16.to_le_bytes());
+                out.extend_from_slice(&voxel.id.to_le_bytes());
+                out.push(voxel.state);
+                out.push(0);
+            }
+            VoxelStorage::Palette { palette, bits, data, .. } => {
+                out.extend_from_slice(&(palette.len() as u16).to_le_bytes());
+                for voxel in palette {
+                    out.extend_from_slice(&voxel.id.to_le_bytes());
+                    out.push(voxel.state);
+                }
+                out.push(*bits as u8);
+                for word in data {
+                    out.extend_from_slice(&word.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    pub fn decode(reader: &mut ByteReader, entry: PaletteEntry) -> Result<Self, RegionError> {
+        let len = reader.u16()? as usize;
+        if len == 0 {
+            return Err(RegionError::BadRecord("empty palette".to_string()));
+        }
+        let mut palette = Vec::with_capacity(len);
+        for _ in 0..len {
+            let voxel = match entry {
+                PaletteEntry::IdU8 => Voxel { id: reader.u8()? as u16, state: 0 },
+                PaletteEntry::IdU16 => Voxel { id: reader.u16()?, state: 0 },
+                PaletteEntry::IdState => Voxel { id: reader.u16()?, state: reader.u8()? },
+            };
+            palette.push(voxel);
+        }
+        let bits = reader.u8()? as u32;
+
+        if bits == 0 {
+            return match palette[..] {
+                [voxel] => Ok(VoxelStorage::Uniform(voxel)),
+                _ => Err(RegionError::BadRecord("palette without indices".to_string())),
+            };
+        }
+        if bits > 16 || bits < bits_for(len) {
+            return Err(RegionError::BadRecord(format!("{} bits for a palette of {}", bits, len)));
+        }
+
+        let mut data = Vec::with_capacity(words_for(bits));
+        for _ in 0..words_for(bits) {
+            data.push(u64::from_le_bytes(reader.bytes(8)?.try_into().unwrap()));
+        }
+        let mut counts = vec![0u16; len];
+        for i in 0..CHUNK_VOL {
+            match counts.get_mut(read_packed(&data, bits, i)) {
+                Some(count) => *count += 1,
+                None => return Err(RegionError::BadRecord("palette index out of range".to_string())),
+            }
+        }
+        Ok(VoxelStorage::Palette { palette, counts, bits, data })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voxel(id: u16) -> Voxel {
+        Voxel { id, state: 0 }
+    }
+
+    fn bits(storage: &VoxelStorage) -> u32 {
+        match storage {
+            VoxelStorage::Uniform(_) => 0,
+            VoxelStorage::Palette { bits, .. } => *bits,
+        }
+    }
+
+    #[test]
+    fn set_and_get_across_bit_growth() {
+        let mut storage = VoxelStorage::Uniform(voxel(0));
+        for i in 0..20 {
+            storage.set(i * 100, voxel(i as u16 + 1));
+        }
+        assert_eq!(bits(&storage), 5);
+        for i in 0..20 {
+            assert_eq!(storage.get(i * 100), voxel(i as u16 + 1));
+            assert_eq!(storage.get(i * 100 + 1), voxel(0));
+        }
+        assert_eq!(storage.iter().filter(|v| v.id != 0).count(), 20);
+    }
+
+    #[test]
+    fn storage_switches_between_uniform_and_palette() {
+        let mut storage = VoxelStorage::from_voxels(&vec![voxel(1); CHUNK_VOL]);
+        assert!(matches!(storage, VoxelStorage::Uniform(v) if v == voxel(1)));
+        storage.set(5, voxel(1));
+        assert!(matches!(storage, VoxelStorage::Uniform(_)));
+
+        storage.set(5, voxel(2));
+        assert_eq!(bits(&storage), 1);
+        assert_eq!(storage.get(5), voxel(2));
+
+        storage.set(5, voxel(1));
+        assert!(matches!(storage, VoxelStorage::Uniform(v) if v == voxel(1)));
+    }
+
+    #[test]
+    fn edited_palette_shrinks_back() {
+        let mut storage = VoxelStorage::Uniform(voxel(0));
+        for i in 0..20 {
+            storage.set(i, voxel(i as u16 + 1));
+        }
+        // убранные блоки освобождают записи, новые занимают их, не расширяя индексы
+        for i in 3..20 {
+            storage.set(i, voxel(0));
+        }
+        assert_eq!(bits(&storage), 2);
+        for i in 3..6 {
+            storage.set(i, voxel(100 + i as u16));
+        }
+        assert_eq!(bits(&storage), 3);
+        assert_eq!(storage.get(4), voxel(104));
+        assert_eq!(storage.get(2), voxel(3));
+
+        for i in 0..6 {
+            storage.set(i, voxel(0));
+        }
+        assert!(matches!(storage, VoxelStorage::Uniform(v) if v == voxel(0)));
+    }
+
+    #[test]
+    fn palette_entries_decode() {
+        let mut storage = VoxelStorage::Uniform(voxel(0));
+        storage.set(1, Voxel { id: 300, state: 2 });
+        storage.set(2, voxel(7));
+        let mut encoded = Vec::new();
+        storage.encode(&mut encoded);
+        let decoded = VoxelStorage::decode(&mut ByteReader::new(&encoded), PaletteEntry::IdState).unwrap();
+        assert!(decoded.iter().eq(storage.iter()));
+
+        // старые записи без состояния: 8- и 16-битные id, 2 бита на индекс
+        let mut words = vec![0u64; words_for(2)];
+        write_packed(&mut words, 2, 1, 1);
+        write_packed(&mut words, 2, 2, 2);
+        let record = |ids: &[u8], width: usize| {
+            let mut out = 3u16.to_le_bytes().to_vec();
+            for &id in ids {
+                out.extend_from_slice(&(id as u16).to_le_bytes()[..width]);
+            }
+            out.push(2);
+            words.iter().for_each(|word| out.extend_from_slice(&word.to_le_bytes()));
+            out
+        };
+        for (entry, width) in [(PaletteEntry::IdU8, 1), (PaletteEntry::IdU16, 2)] {
+            let decoded = VoxelStorage::decode(&mut ByteReader::new(&record(&[0, 5, 9], width)), entry).unwrap();
+            assert_eq!([0, 1, 2, 3].map(|i| decoded.get(i).id), [0, 5, 9, 0]);
+        }
+
+        let mut bad = record(&[0, 5, 9], 2);
+        bad[0] = 2;
+        assert!(VoxelStorage::decode(&mut ByteReader::new(&bad), PaletteEntry::IdU16).is_err());
+    }
+}
@@ -1,4 +1,71 @@
-#[derive(Clone, Copy, Debug)]
+use std::fmt;
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct Voxel {
-    pub id: u8,
-}
\ No newline at end of file
+    pub id: u16,
+    /// Состояние блока: поворот, вариант и т.п., см. `BlockStates`
+    pub state: u8,
+}
+
+
+/// id блока не помещается в u16
+#[derive(Debug, Clone, Copy)]
+pub struct BlockIdOutOfRange(pub i32);
+
+
+impl fmt::Display for BlockIdOutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block id {} is out of range 0..={}", self.0, u16::MAX)
+    }
+}
+
+
+impl std::error::Error for BlockIdOutOfRange {}
+
+
+/// Воксель, который нельзя поставить в мир
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidVoxel {
+    IdOutOfRange(i32),
+    UnknownBlock(u16),
+    /// Состояние не меньше числа состояний блока
+    StateOutOfRange { id: u16, state: u8, count: u8 },
+}
+
+
+impl fmt::Display for InvalidVoxel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IdOutOfRange(id) => BlockIdOutOfRange(*id).fmt(f),
+            Self::UnknownBlock(id) => write!(f, "unknown block id {}", id),
+            Self::StateOutOfRange { id, state, count } => {
+                write!(f, "block {} has {} states, got state {}", id, count, state)
+            }
+        }
+    }
+}
+
+
+impl std::error::Error for InvalidVoxel {}
+
+
+impl From<BlockIdOutOfRange> for InvalidVoxel {
+    fn from(err: BlockIdOutOfRange) -> Self {
+        Self::IdOutOfRange(err.0)
+    }
+}
+
+
+impl Voxel {
+    pub fn from_id(id: i32) -> Result<Self, BlockIdOutOfRange> {
+        Self::with_state(id, 0)
+    }
+
+    pub fn with_state(id: i32, state: u8) -> Result<Self, BlockIdOutOfRange> {
+        match u16::try_from(id) {
+            Ok(id) => Ok(Voxel { id, state }),
+            Err(_) => Err(BlockIdOutOfRange(id)),
+        }
+    }
+}
@@ -3,6 +3,9 @@ use glam::{Mat4, Vec3, Quat};
 
 pub struct Camera {
     pub fov: f32,
+    /// Ближняя и дальняя плоскости отсечения
+    pub near: f32,
+    pub far: f32,
     pub position: Vec3,
     pub front: Vec3,
     pub up: Vec3,
@@ -18,7 +21,7 @@ impl Camera {
         let right = rotation.mul_vec3(Vec3::X);
         let up = rotation.mul_vec3(Vec3::Y);
 
-        Self { position, fov, rotation, front, right, up }
+        Self { position, fov, near: 0.1, far: 100.0, rotation, front, right, up }
     }
 
 
@@ -37,7 +40,7 @@ impl Camera {
 
     pub fn get_projection(&self, width: f32, height: f32) -> Mat4 {
         let aspect = width / height;
-        Mat4::perspective_rh(self.fov, aspect, 0.1, 100.0)
+        Mat4::perspective_rh(self.fov, aspect, self.near, self.far)
     }
 
 
@@ -50,6 +50,10 @@ impl Window {
         self.window.should_close()
     }
 
+    pub fn set_title(&mut self, title: &str){
+        self.window.set_title(title);
+    }
+
     pub fn close(&mut self){
         self.window.set_should_close(true);
     }
@@ -85,6 +89,13 @@ impl Window {
         }
     }
 
+    /// Включает и выключает запись в буфер глубины, прозрачные грани рисуются без неё
+    pub fn depth_write(&self, enabled: bool) {
+        unsafe {
+            gl::DepthMask(if enabled { gl::TRUE } else { gl::FALSE });
+        }
+    }
+
     pub fn swap_interval(&self, interval: c_int) {
         unsafe {
             glfwSwapInterval(interval);